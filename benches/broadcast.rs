@@ -0,0 +1,82 @@
+//! compares encoding a broadcast for every session with encoding it once and
+//! sharing the frame. Run with `cargo bench --bench broadcast [-- <lobby sizes>...]`.
+
+use std::time::{Duration, Instant};
+
+use ball_pass_game::{
+    encoding::{Frame, NetworkMessage},
+    game::Player,
+    message::{ToClient, WhoPassed},
+};
+use bytes::BytesMut;
+use tokio_util::codec::Encoder;
+
+/// latencies of this many players still fit in a single frame
+const MAX_LATENCIES: usize = 3_000;
+/// recipients and bytes to encode for per measurement, spread over repeated
+/// broadcasts, whichever limit is reached first
+const RECIPIENTS: usize = 1_000_000;
+const BYTES: usize = 256 << 20;
+
+/// clone and encode the message for every session, as sessions used to
+fn per_session(msg: &ToClient, lobby: usize, buf: &mut BytesMut) {
+    let mut codec = NetworkMessage::<ToClient>::new();
+    for _ in 0..lobby {
+        codec.encode(msg.clone(), buf).expect("message fits a frame");
+        buf.clear();
+    }
+}
+
+/// encode the message once and write the shared frame for every session
+fn encoded_once(msg: &ToClient, lobby: usize, buf: &mut BytesMut) {
+    let mut codec = NetworkMessage::<ToClient>::new();
+    let frame = Frame::encode(msg).expect("message fits a frame");
+    for _ in 0..lobby {
+        codec.encode(frame.clone(), buf).expect("frames always encode");
+        buf.clear();
+    }
+}
+
+/// time per broadcast to the whole lobby
+fn measure(broadcast: fn(&ToClient, usize, &mut BytesMut), msg: &ToClient, lobby: usize) -> Duration {
+    let mut buf = BytesMut::new();
+    NetworkMessage::new().encode(msg.clone(), &mut buf).expect("message fits a frame");
+    let rounds = (RECIPIENTS / lobby).min(BYTES / (lobby * buf.len())).max(1);
+    buf.clear();
+    let started = Instant::now();
+    for _ in 0..rounds {
+        broadcast(msg, lobby, &mut buf);
+    }
+    started.elapsed() / rounds as u32
+}
+
+fn main() {
+    let lobbies: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let lobbies = if lobbies.is_empty() { vec![100, 1_000, 10_000] } else { lobbies };
+
+    for lobby in lobbies {
+        let latencies = (0..lobby.min(MAX_LATENCIES))
+            .map(|id| (Player(id), Duration::from_micros(id as u64 * 37)))
+            .collect();
+        let messages = [
+            ("pass", ToClient::PassBall(Player(lobby / 2), WhoPassed::Player)),
+            ("latencies", ToClient::PlayerLatencies(latencies)),
+        ];
+
+        for (name, msg) in &messages {
+            let before = measure(per_session, msg, lobby);
+            let after = measure(encoded_once, msg, lobby);
+            println!(
+                "{:<9} to {:>6} players: per session {:>10.1}µs, encoded once {:>10.1}µs ({:.1}x)",
+                name,
+                lobby,
+                before.as_secs_f64() * 1e6,
+                after.as_secs_f64() * 1e6,
+                before.as_secs_f64() / after.as_secs_f64()
+            );
+        }
+    }
+}
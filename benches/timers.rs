@@ -0,0 +1,150 @@
+//! compares sessions running their own `EventQueue` timers with sessions sharing
+//! a `TimerWheel`. Run with `cargo bench --bench timers [-- <sessions>...]`.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use ball_pass_game::{
+    clock::Clock,
+    events::{EventQueue, TimerWheel},
+};
+use futures_util::future;
+
+/// heartbeat and ping period, shortened so a few seconds show plenty of ticks
+const PERIOD: Duration = Duration::from_millis(50);
+const TIMEOUT: Duration = Duration::from_millis(200);
+const RUN_FOR: Duration = Duration::from_secs(3);
+
+enum Tick {
+    CheckHeartBeat,
+    Heartbeat,
+    Ping,
+}
+
+/// what one simulated session saw
+#[derive(Default)]
+struct Report {
+    ticks: u64,
+    /// how much later than a period after the previous one each heartbeat came
+    late: Vec<Duration>,
+    last_heartbeat: Option<Instant>,
+}
+
+impl Report {
+    fn record(&mut self, tick: &Tick) {
+        self.ticks += 1;
+        if let Tick::Heartbeat = tick {
+            let now = Instant::now();
+            if let Some(last) = self.last_heartbeat.replace(now) {
+                self.late.push((now - last).saturating_sub(PERIOD));
+            }
+        }
+    }
+}
+
+/// a session with an event queue for its timers, how sessions used to work
+async fn own_queue(end: Instant) -> Report {
+    let mut ticks = EventQueue::with_clock(Clock::System);
+    let check = ticks.sender().send_with_delay(Tick::CheckHeartBeat, TIMEOUT);
+    ticks.sender().send_every(|| Tick::Heartbeat, PERIOD);
+    ticks.sender().send_every(|| Tick::Ping, PERIOD);
+
+    let mut report = Report::default();
+    while let Ok(Some(tick)) = tokio::time::timeout_at(end.into(), ticks.recv_async()).await {
+        report.record(&tick);
+        // the client answers every heartbeat
+        if let Tick::Heartbeat = tick {
+            check.reschedule(TIMEOUT);
+        }
+    }
+    report
+}
+
+/// a session registering its timers with a wheel shared by all sessions
+async fn shared_wheel(wheel: TimerWheel, end: Instant) -> Report {
+    let (tx, ticks) = flume::unbounded();
+    let check = wheel.send_with_delay(&tx, Tick::CheckHeartBeat, TIMEOUT);
+    wheel.send_every(&tx, || Tick::Heartbeat, PERIOD);
+    wheel.send_every(&tx, || Tick::Ping, PERIOD);
+
+    let mut report = Report::default();
+    while let Ok(Ok(tick)) = tokio::time::timeout_at(end.into(), ticks.recv_async()).await {
+        report.record(&tick);
+        if let Tick::Heartbeat = tick {
+            check.reschedule(TIMEOUT);
+        }
+    }
+    report
+}
+
+async fn bench<F, S>(name: &str, sessions: usize, session: F)
+where
+    F: Fn(Instant) -> S,
+    S: Future<Output = Report> + Send + 'static,
+{
+    let cpu = cpu_time();
+    let started = Instant::now();
+    let end = started + RUN_FOR;
+    let tasks = (0..sessions).map(|_| tokio::spawn(session(end)));
+    let reports: Vec<Report> = future::join_all(tasks)
+        .await
+        .into_iter()
+        .map(|report| report.expect("session panicked"))
+        .collect();
+    let elapsed = started.elapsed();
+
+    let ticks: u64 = reports.iter().map(|r| r.ticks).sum();
+    let mut late: Vec<Duration> = reports.into_iter().flat_map(|r| r.late).collect();
+    late.sort_unstable();
+    let at = |p: f64| {
+        let index = ((late.len().max(1) - 1) as f64 * p).round() as usize;
+        late.get(index).map_or(0.0, |late| late.as_secs_f64() * 1000.0)
+    };
+    let cpu = match (cpu, cpu_time()) {
+        (Some(before), Some(after)) => format!("{:.2}s", (after - before).as_secs_f64()),
+        _ => "n/a".to_owned(),
+    };
+
+    println!(
+        "{:<12} {:>6} sessions: {:>9} ticks in {:.2}s, cpu {}, heartbeat lateness p50 {:.2}ms p99 {:.2}ms",
+        name,
+        sessions,
+        ticks,
+        elapsed.as_secs_f64(),
+        cpu,
+        at(0.5),
+        at(0.99)
+    );
+}
+
+/// user and system time of this process so far, where /proc has it
+fn cpu_time() -> Option<Duration> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the fields after the command name, starting with the state
+    let fields: Vec<&str> = stat.rsplit(')').next()?.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    // clock ticks, nearly always 100 per second on Linux
+    Some(Duration::from_millis((utime + stime) * 10))
+}
+
+#[tokio::main]
+async fn main() {
+    let sessions: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let sessions = if sessions.is_empty() { vec![1_000, 10_000] } else { sessions };
+
+    for count in sessions {
+        bench("own queue", count, own_queue).await;
+
+        let (wheel, driver) = TimerWheel::new(Clock::System);
+        let driver = tokio::spawn(driver.run());
+        bench("shared wheel", count, |end| shared_wheel(wheel.clone(), end)).await;
+        drop(wheel);
+        driver.await.expect("wheel driver panicked");
+    }
+}
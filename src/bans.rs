@@ -0,0 +1,172 @@
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer};
+
+pub type Result<T> = std::result::Result<T, Error>;
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("could not read ban list {0}")]
+    IO(PathBuf, #[source] std::io::Error),
+
+    #[error("could not parse ban list {0}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+}
+
+// ban list file format:
+//
+// [[ban]]
+// ip = "203.0.113.0/24"      # single address or CIDR range
+// reason = "spamming passes"
+// expires = "2030-01-01T00:00:00Z"  # optional, permanent if missing
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BanFile {
+    #[serde(default)]
+    ban: Vec<Ban>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ban {
+    #[serde(deserialize_with = "deserialize_net")]
+    pub ip: IpNet,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default, with = "humantime_serde")]
+    pub expires: Option<SystemTime>,
+}
+
+impl Ban {
+    fn is_active(&self, now: SystemTime) -> bool { self.expires.is_none_or(|at| now < at) }
+
+    /// message sent to a client rejected by this ban
+    pub fn message(&self) -> String {
+        let mut msg = "You are banned".to_owned();
+        if !self.reason.is_empty() {
+            msg.push_str(": ");
+            msg.push_str(&self.reason);
+        }
+        if let Some(expires) = self.expires {
+            msg.push_str(&format!(" (until {})", humantime::format_rfc3339_seconds(expires)));
+        }
+        msg
+    }
+}
+
+/// accept both plain addresses and CIDR ranges
+fn deserialize_net<'de, D: Deserializer<'de>>(de: D) -> std::result::Result<IpNet, D::Error> {
+    let value = String::deserialize(de)?;
+    IpNet::from_str(&value)
+        .or_else(|_| IpAddr::from_str(&value).map(IpNet::from))
+        .map_err(|_| serde::de::Error::custom(format!("invalid address or range `{}`", value)))
+}
+
+/// IP bans loaded from a file, reloaded when the file is edited at runtime
+#[derive(Debug, Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: Vec<Ban>,
+}
+
+impl BanList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut list = Self {
+            path: Some(path.to_owned()),
+            bans: Vec::new(),
+        };
+        list.reload()?;
+        Ok(list)
+    }
+
+    /// re-read the ban list file, keeping the current bans if it is invalid
+    pub fn reload(&mut self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let contents =
+            std::fs::read_to_string(path).map_err(|err| Error::IO(path.to_owned(), err))?;
+        let file: BanFile =
+            toml::from_str(&contents).map_err(|err| Error::Parse(path.to_owned(), err))?;
+
+        self.bans = file.ban;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize { self.bans.len() }
+
+    pub fn is_empty(&self) -> bool { self.bans.is_empty() }
+
+    /// the active ban covering `ip`, if any
+    pub fn check(&self, ip: IpAddr) -> Option<&Ban> {
+        let now = SystemTime::now();
+        self.bans
+            .iter()
+            .find(|ban| ban.is_active(now) && ban.ip.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ban(ip: &str, expires: Option<SystemTime>) -> Ban {
+        Ban {
+            ip: ip.parse().unwrap(),
+            reason: String::new(),
+            expires,
+        }
+    }
+
+    #[test]
+    fn bans_accept_addresses_and_ranges() {
+        let parse = |ip: &str| toml::from_str::<BanFile>(&format!("[[ban]]\nip = \"{}\"", ip));
+        assert_eq!(parse("203.0.113.7").unwrap().ban[0].ip, "203.0.113.7/32".parse().unwrap());
+        assert_eq!(parse("2001:db8::1").unwrap().ban[0].ip, "2001:db8::1/128".parse().unwrap());
+        assert_eq!(parse("203.0.113.0/24").unwrap().ban[0].ip, "203.0.113.0/24".parse().unwrap());
+        assert!(parse("not an address").is_err());
+    }
+
+    #[test]
+    fn expired_bans_are_ignored() {
+        let now = SystemTime::now();
+        let list = BanList {
+            path: None,
+            bans: vec![
+                ban("203.0.113.1/32", Some(now - Duration::from_secs(60))),
+                ban("203.0.113.2/32", Some(now + Duration::from_secs(60))),
+                ban("203.0.113.3/32", None),
+            ],
+        };
+
+        assert!(list.check("203.0.113.1".parse().unwrap()).is_none());
+        assert!(list.check("203.0.113.2".parse().unwrap()).is_some());
+        assert!(list.check("203.0.113.3".parse().unwrap()).is_some());
+        assert!(list.check("198.51.100.1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_previous_bans() {
+        let path = std::env::temp_dir().join(format!("bans-{}.toml", std::process::id()));
+        std::fs::write(&path, "[[ban]]\nip = \"203.0.113.0/24\"\n").unwrap();
+        let mut list = BanList::load(&path).unwrap();
+
+        std::fs::write(&path, "[[ban]]\nip = \"203.0.113.0/\"\n").unwrap();
+        let reloaded = list.reload();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(reloaded, Err(Error::Parse(..))));
+        assert_eq!(list.len(), 1);
+        assert!(list.check("203.0.113.9".parse().unwrap()).is_some());
+        assert!(matches!(list.reload(), Err(Error::IO(..))));
+        assert_eq!(list.len(), 1);
+    }
+}
@@ -0,0 +1,98 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use crate::{command::Target, game::Player};
+
+/// how a bot client passes the ball on, see `client --bot`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// pass to a random other player
+    Random,
+    /// pass to the player with the next higher ID
+    Next,
+    /// hold the ball for a while, then pass to the next player
+    Hold(Duration),
+    /// pass back to whoever passed the ball, or the next player if the server did
+    Echo,
+}
+
+impl Strategy {
+    /// how long to keep the ball before passing it on
+    pub fn hold_time(&self, think_time: Duration) -> Duration {
+        match self {
+            Strategy::Hold(duration) => *duration,
+            _ => think_time,
+        }
+    }
+
+    /// who to pass to, given who passed the ball to us
+    pub fn target(&self, from: Option<Player>) -> Target {
+        match (self, from) {
+            (Strategy::Random, _) => Target::Random,
+            (Strategy::Echo, Some(from)) => Target::Player(from),
+            _ => Target::Next,
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "random" => Ok(Strategy::Random),
+            "next" => Ok(Strategy::Next),
+            "echo" => Ok(Strategy::Echo),
+            _ => {
+                let hold = value.strip_prefix("hold:").ok_or_else(|| {
+                    format!(
+                        "unknown bot strategy `{}`, expected random, next, echo or hold:<duration>",
+                        value
+                    )
+                })?;
+                hold.parse()
+                    .map(Duration::from_secs)
+                    .or_else(|_| humantime::parse_duration(hold))
+                    .map(Strategy::Hold)
+                    .map_err(|_| format!("invalid hold time `{}`, e.g. `hold:5` or `hold:1500ms`", hold))
+            }
+        }
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Strategy::Random => write!(f, "random"),
+            Strategy::Next => write!(f, "next"),
+            Strategy::Hold(duration) => write!(f, "hold:{}", humantime::format_duration(*duration)),
+            Strategy::Echo => write!(f, "echo"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!("random".parse(), Ok(Strategy::Random));
+        assert_eq!("next".parse(), Ok(Strategy::Next));
+        assert_eq!("echo".parse(), Ok(Strategy::Echo));
+        assert_eq!("hold:5".parse(), Ok(Strategy::Hold(Duration::from_secs(5))));
+        assert_eq!("hold:1500ms".parse(), Ok(Strategy::Hold(Duration::from_millis(1500))));
+    }
+
+    #[test]
+    fn rejects_invalid_strategies() {
+        assert!("bounce".parse::<Strategy>().is_err());
+        assert!("hold".parse::<Strategy>().is_err());
+        assert!("hold:soon".parse::<Strategy>().is_err());
+    }
+
+    #[test]
+    fn echo_passes_back_to_sender() {
+        assert_eq!(Strategy::Echo.target(Some(Player(4))), Target::Player(Player(4)));
+        assert_eq!(Strategy::Echo.target(None), Target::Next);
+    }
+}
@@ -1,54 +1,145 @@
-use std::{io::BufRead, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, BufRead},
+    time::Duration,
+};
 
-use argh::FromArgs;
-use futures_util::{SinkExt, StreamExt, TryFutureExt};
-use tokio::net::TcpStream;
+use crossterm::event::KeyEvent;
+use rand::seq::SliceRandom;
+use tracing::warn;
 
 use crate::{
+    bot::Strategy,
+    command::{Command, ParseError, Target, COMMANDS, HELP},
+    connection::{self, ConnectOptions, ConnectionEvent},
     events::{EventQueue, EventSender},
     game::Player,
-    message::{GameState, ToClient, ToServer, WhoPassed},
-    utils,
+    message::{Action, GameState, RejectReason, ToClient, ToServer, WhoPassed},
+    tui::{KeyAction, Tui, View},
 };
 
-/// run client
-#[derive(FromArgs, Default)]
-#[argh(subcommand, name = "client")]
-pub struct CliOpts {
-    /// address of server to connect to.
-    #[argh(option, short = 'h')]
-    pub server_addr: String,
+/// interactive client options
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    pub connect: ConnectOptions,
+    /// use the full-screen terminal interface
+    pub tui: bool,
+    /// play automatically instead of reading commands
+    pub bot: Option<Strategy>,
+    /// how long a bot waits before passing the ball on
+    pub think_time: Option<Duration>,
 }
 
+/// default time a bot holds the ball, keeping bots passing among each other under the rate limit
+const BOT_THINK_TIME: Duration = Duration::from_millis(500);
+
 pub enum Event {
     Input(String),
-    ServerMessage(ToClient),
-    ConnectionDropped,
+    /// key press in the terminal interface
+    Key(KeyEvent),
+    /// the terminal was resized
+    Redraw,
+    Connection(ConnectionEvent),
+    /// time for the bot to pass the ball, ignored unless it is the latest turn
+    BotTurn(u64),
     CtrlC,
 }
 
+impl From<ConnectionEvent> for Event {
+    fn from(event: ConnectionEvent) -> Self { Event::Connection(event) }
+}
+
+const NOT_JOINED: &str = "you have not joined the game yet";
+
+/// where the client prints what happens in the game
+enum Output {
+    Plain,
+    Tui(Box<Tui>),
+}
+
+impl Output {
+    fn print(&mut self, line: String) {
+        match self {
+            Output::Plain => println!("{}", line),
+            Output::Tui(tui) => tui.print(line),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Connection {
+    Connecting,
+    Queued(usize),
+    Playing,
+    Reconnecting(u32),
+    Disconnected,
+}
+
+impl Display for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Connection::Connecting => write!(f, "connecting"),
+            Connection::Queued(position) => write!(f, "queued #{}", position),
+            Connection::Playing => write!(f, "connected"),
+            Connection::Reconnecting(attempt) => write!(f, "reconnecting (attempt {})", attempt),
+            Connection::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
 struct Game {
     myself: Player,
     state: GameState,
+    /// the player who passed the ball to its holder, if it was not the server
+    passed_by: Option<Player>,
+    /// round trip times to the server, as measured by the server
+    latencies: HashMap<Player, Duration>,
+}
+
+struct Bot {
+    strategy: Strategy,
+    think_time: Duration,
+    turn: u64,
 }
 
 pub struct ClientApp {
     event_queue: EventQueue<Event>,
     server_tx: EventSender<ToServer>,
     game: Option<Game>,
-    // should_exit: bool,
+    server_addr: String,
+    connection: Connection,
+    out: Output,
+    bot: Option<Bot>,
+    /// our own round trip time to the server
+    latency: Option<Duration>,
 }
 
-pub async fn start(addr: String) -> ClientApp {
+pub async fn start(opts: ClientOptions) -> io::Result<ClientApp> {
     let event_queue: EventQueue<Event> = EventQueue::default();
     let app_tx = event_queue.sender().clone();
 
-    ClientApp {
+    let think_time = opts.think_time.unwrap_or(BOT_THINK_TIME);
+    let out = if opts.tui {
+        Output::Tui(Box::new(Tui::start()?))
+    } else {
+        Output::Plain
+    };
+
+    Ok(ClientApp {
         event_queue,
         game: None,
-        // should_exit: false,
-        server_tx: connect_to_server(addr, app_tx).await,
-    }
+        server_addr: opts.connect.server_addr.clone(),
+        server_tx: connection::connect_with(opts.connect, app_tx),
+        connection: Connection::Connecting,
+        out,
+        bot: opts.bot.map(|strategy| Bot {
+            strategy,
+            think_time,
+            turn: 0,
+        }),
+        latency: None,
+    })
 }
 
 impl ClientApp {
@@ -57,80 +148,49 @@ impl ClientApp {
     pub async fn run_loop(&mut self) {
         let sender = self.event_queue.sender().clone();
 
-        std::thread::spawn(move || {
-            let stdin = std::io::stdin();
-            let mut iter = stdin.lock().lines();
+        if let Output::Tui(_) = self.out {
+            Tui::spawn_input(sender);
+        } else if let Some(bot) = &self.bot {
+            self.out.print(format!("🤖 Playing as a bot with the {} strategy.", bot.strategy));
+        } else {
+            std::thread::spawn(move || {
+                let stdin = std::io::stdin();
+                let mut iter = stdin.lock().lines();
 
-            while let Some(Ok(line)) = iter.next() {
-                sender.send(Event::Input(line));
-            }
+                while let Some(Ok(line)) = iter.next() {
+                    sender.send(Event::Input(line));
+                }
 
-            sender.send_with_urgency(Event::CtrlC);
-        });
+                sender.send_with_urgency(Event::CtrlC);
+            });
+        }
 
+        self.draw();
         loop {
             match self.event_queue.recv_async().await.unwrap() {
-                // handle server message
-                Event::ServerMessage(server_msg) => match server_msg {
-                    ToClient::InitialState(player, state) => {
-                        self.game = Some(Game {
-                            myself: player,
-                            state,
-                        })
-                    }
-                    ToClient::PlayerJoin(pl) => {
-                        if let Some(ref mut game) = self.game {
-                            println!("{} joined the game.", pl);
-                            game.state.players.push(pl)
-                        }
-                    }
-                    ToClient::PlayerLeave(pl) => {
-                        if let Some(ref mut game) = self.game {
-                            println!("(pass) {} -> SERVER", pl);
-                            println!("{} left the game.", pl);
-                            game.state.players.retain(|opl| opl == &pl)
-                        }
-                    }
-                    ToClient::PassBall(to, who) => {
-                        if let Some(game) = &mut self.game {
-                            let from = game.state.info.player_with_ball;
-                            game.state.info.player_with_ball.replace(to);
-                            match who {
-                                WhoPassed::Player => {
-                                    println!("[PASS] {} -> {}", from.unwrap(), to)
-                                }
-                                WhoPassed::PlayerWithBallLeft => {
-                                    println!("[PASS] SERVER -> {} (auto pass)", to,)
-                                }
-                                WhoPassed::PlayerStumbledUponBall => {
-                                    println!("[PASS] SERVER -> you");
-                                    println!("✨ You have the ball!");
-                                }
-                            };
-                        }
-                    }
-                    ToClient::Disconnect(reason) => {
-                        println!("You were disconnected from server: {}", reason)
+                Event::Connection(event) => {
+                    if !self.on_connection_event(event) {
+                        break;
                     }
-                },
+                }
 
                 // handle input events
-                Event::Input(input) => {
-                    let args: Vec<&str> = input.split(" ").collect();
-                    if !args.is_empty() && args[0] == "pass" {
-                        if let Ok(id) = args[1].parse() {
-                            self.server_tx.send(ToServer::PassBall(Player(id)));
-                            continue;
+                Event::Input(input) => self.on_input(&input),
+
+                Event::Key(key) => {
+                    if let Output::Tui(tui) = &mut self.out {
+                        let players = self.game.as_ref().map_or(&[][..], |g| &g.state.players);
+                        match tui.on_key(key, players, COMMANDS) {
+                            KeyAction::Submit(input) => self.on_input(&input),
+                            KeyAction::Quit => self.event_queue.sender().send_with_urgency(Event::CtrlC),
+                            KeyAction::None => {}
                         }
                     }
-                    println!("invalid input: enter pass <id> or ctrlc to stop")
                 }
 
-                // handle server connection drop
-                Event::ConnectionDropped => {
-                    println!("✨ Server connection dropped. Stopping..");
-                    break;
-                }
+                Event::Redraw => {}
+
+                Event::BotTurn(turn) => self.on_bot_turn(turn),
 
                 // close on ctrl-c
                 Event::CtrlC => {
@@ -139,62 +199,372 @@ impl ClientApp {
                     // stop
                 }
             }
+            self.draw();
         }
     }
-}
 
-async fn connect_to_server(
-    server_addr: String,
-    app_tx: EventSender<Event>,
-) -> EventSender<ToServer> {
-    let mut server_msg_queue: EventQueue<ToServer> = EventQueue::default();
-    let server_tx = server_msg_queue.sender().clone();
-
-    // start connection to server
-    let socket = TcpStream::connect(server_addr.clone())
-        .map_ok(utils::frame_socket::<ToClient, ToServer>)
-        .await
-        .unwrap_or_else(|_| panic!("Could not connect to the server {}", server_addr));
-
-    let connection_loop = async move {
-        let (mut server_to_client, mut client_to_server) = socket;
-
-        // send heartbeats every couple seconds otherwise server will disconnect
-        let mut heartbeat = tokio::time::interval(Duration::from_secs(2));
-        loop {
-            tokio::select! {
-                _ = heartbeat.tick() => server_msg_queue.sender().send(ToServer::Heartbeat),
+    /// returns whether to keep running
+    fn on_connection_event(&mut self, event: ConnectionEvent) -> bool {
+        match event {
+            // handle server message
+            ConnectionEvent::Message(server_msg) => self.on_server_message(server_msg),
 
-                Some(to_server_msg) = server_msg_queue.recv_async() => {
-                    if let ToServer::Leave = to_server_msg{
-                        let _ = client_to_server.send(to_server_msg).await;
-                        break;
-                    } else if client_to_server.send(to_server_msg).await.is_err() {
-                        break ;
+            ConnectionEvent::Latency(rtt) => self.latency = Some(rtt),
+
+            ConnectionEvent::Connected => {
+                self.connection = Connection::Connecting;
+                self.out.print(format!("✨ Connected to {}.", self.server_addr));
+            }
+
+            // the game state is sent again once we rejoin
+            ConnectionEvent::Reconnecting {
+                reason,
+                attempt,
+                delay,
+            } => {
+                self.game = None;
+                self.latency = None;
+                self.connection = Connection::Reconnecting(attempt);
+                self.out.print(format!(
+                    "⚠ {}, reconnecting in {:.1}s (attempt {})..",
+                    reason,
+                    delay.as_secs_f64(),
+                    attempt
+                ));
+            }
+
+            // handle server connection drop
+            ConnectionEvent::Dropped(reason) => {
+                self.connection = Connection::Disconnected;
+                if let Output::Tui(_) = self.out {
+                    // keep the screen up so the player can read why
+                    self.out.print(format!(
+                        "✨ Server connection dropped: {}. Press Ctrl-C to exit.",
+                        reason
+                    ));
+                } else {
+                    self.out
+                        .print(format!("✨ Server connection dropped: {}. Stopping..", reason));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn on_server_message(&mut self, server_msg: ToClient) {
+        match server_msg {
+            ToClient::InitialState(player, state) => {
+                self.out.print(format!("✨ You joined the game as {}.", player));
+                self.connection = Connection::Playing;
+                self.game = Some(Game {
+                    myself: player,
+                    state,
+                    passed_by: None,
+                    latencies: HashMap::new(),
+                })
+            }
+            ToClient::Queued { position } => {
+                self.connection = Connection::Queued(position);
+                self.out.print(format!(
+                    "⏳ The game is full, you are #{} in the queue.",
+                    position
+                ))
+            }
+            ToClient::PlayerJoin(pl) => {
+                if let Some(ref mut game) = self.game {
+                    self.out.print(format!("{} joined the game.", pl));
+                    game.state.players.push(pl);
+
+                    // a bot holding the ball alone can pass it now
+                    let holding = game.state.info.player_with_ball == Some(game.myself);
+                    if holding && game.state.players.len() == 2 {
+                        self.schedule_bot_turn();
                     }
                 }
+            }
+            ToClient::PlayerLeave(pl) => {
+                if let Some(ref mut game) = self.game {
+                    self.out.print(format!("{} left the game.", pl));
+                    game.state.players.retain(|opl| opl != &pl);
+                    game.latencies.remove(&pl);
+                    if game.passed_by == Some(pl) {
+                        game.passed_by = None;
+                    }
+                }
+            }
+            ToClient::PassBall(to, who) => {
+                if let Some(game) = &mut self.game {
+                    let from = game.state.info.player_with_ball;
+                    game.state.info.player_with_ball.replace(to);
+                    game.passed_by = from.filter(|_| matches!(who, WhoPassed::Player));
+                    let line = match (who, from) {
+                        (WhoPassed::Player, Some(from)) => format!("[PASS] {} -> {}", from, to),
+                        (WhoPassed::PlayerWithBallLeft, _) => {
+                            format!("[PASS] SERVER -> {} (auto pass)", to)
+                        }
+                        (WhoPassed::HoldLimitReached, _) => {
+                            format!("[PASS] SERVER -> {} (held too long)", to)
+                        }
+                        _ => format!("[PASS] SERVER -> {}", to),
+                    };
+                    self.out.print(line);
 
-                Some(server_msg) = server_to_client.next() => {
-                    match server_msg {
-                        Ok(ToClient::Disconnect(reason))  => {
-                            app_tx.send(Event::ServerMessage(ToClient::Disconnect(reason)));
-                            break
-                        },
+                    if to == game.myself {
+                        self.out.print("✨ You have the ball!".into());
+                        self.schedule_bot_turn();
+                    }
+                }
+            }
+            ToClient::ActionRejected { action, reason } => {
+                let Action::PassBall(to) = action;
+                self.out.print(format!(
+                    "✗ Could not pass the ball to {}: {}",
+                    to,
+                    describe(reason)
+                ));
+
+                // a bot that still holds the ball tries again, e.g. after its target left
+                if let Some(game) = &self.game {
+                    if game.state.info.player_with_ball == Some(game.myself) {
+                        self.schedule_bot_turn();
+                    }
+                }
+            }
+            ToClient::Throttled {
+                kind,
+                warnings_left,
+            } => self.out.print(format!(
+                "⚠ Slow down! Too many {:?} messages, {} warnings left before you are kicked.",
+                kind, warnings_left
+            )),
+            ToClient::ShuttingDown { in_secs } => {
+                self.out.print(format!("⚠ Server shutting down in {}s..", in_secs))
+            }
+            ToClient::PlayerLatencies(latencies) => {
+                if let Some(game) = &mut self.game {
+                    game.latencies.extend(latencies);
+                }
+            }
+            // handled by the connection task
+            ToClient::Hello { .. }
+            | ToClient::Heartbeat
+            | ToClient::Ping(..)
+            | ToClient::Pong(..) => {}
+            ToClient::Disconnect(reason) => self.out.print(format!(
+                "You were disconnected from server: {}",
+                reason
+            )),
+            ToClient::Kicked(reason) => self.out.print(format!(
+                "You were removed from the server: {}",
+                reason
+            )),
+        }
+    }
 
-                        Ok(msg) => app_tx.send(Event::ServerMessage(msg)),
+    fn on_input(&mut self, input: &str) {
+        let command = match input.parse() {
+            Ok(command) => command,
+            Err(ParseError::Empty) => return,
+            Err(err) => return self.out.print(format!("✗ {}", err)),
+        };
 
-                        _ => break,
+        match command {
+            Command::Help => HELP.lines().for_each(|line| self.out.print(line.into())),
+            Command::Pass(target) => match self.resolve(target) {
+                Ok(to) => {
+                    self.server_tx.send(ToServer::PassBall(to));
+                }
+                Err(err) => self.out.print(format!("✗ {}", err)),
+            },
+            Command::Players => match &self.game {
+                Some(game) => {
+                    let mut players = game.state.players.clone();
+                    players.sort_by_key(|p| p.0);
+                    let holder = game.state.info.player_with_ball;
+                    self.out.print(format!("{} players:", players.len()));
+                    for player in players {
+                        let you = if player == game.myself { " (you)" } else { "" };
+                        let ball = if Some(player) == holder { " has the ball" } else { "" };
+                        let latency = match game.latencies.get(&player) {
+                            Some(rtt) => format!(" ({}ms)", rtt.as_millis()),
+                            None => String::new(),
+                        };
+                        self.out.print(format!("  {}{}{}{}", player, you, ball, latency));
+                    }
+                }
+                None => self.out.print(NOT_JOINED.into()),
+            },
+            Command::WhoAmI => match &self.game {
+                Some(game) => self.out.print(format!("You are {}.", game.myself)),
+                None => self.out.print(NOT_JOINED.into()),
+            },
+            Command::Status => {
+                let mut status = format!("{} at {}", self.connection, self.server_addr);
+                if let Some(rtt) = self.latency {
+                    status.push_str(&format!(" ({}ms)", rtt.as_millis()));
+                }
+                if let Some(game) = &self.game {
+                    let holder = match game.state.info.player_with_ball {
+                        Some(holder) if holder == game.myself => "you".into(),
+                        Some(holder) => holder.to_string(),
+                        None => "nobody".into(),
                     };
+                    status.push_str(&format!(
+                        ", {} players, ball held by {}",
+                        game.state.players.len(),
+                        holder
+                    ));
                 }
+                self.out.print(status)
+            }
+            Command::Quit => self.event_queue.sender().send_with_urgency(Event::CtrlC),
+        }
+    }
+
+    /// let the bot pass the ball after its hold time, if playing as a bot
+    fn schedule_bot_turn(&mut self) {
+        if let Some(bot) = &mut self.bot {
+            bot.turn += 1;
+            let delay = bot.strategy.hold_time(bot.think_time);
+            self.event_queue
+                .sender()
+                .send_with_delay(Event::BotTurn(bot.turn), delay);
+        }
+    }
+
+    fn on_bot_turn(&mut self, turn: u64) {
+        let (strategy, game) = match (&self.bot, &self.game) {
+            (Some(bot), Some(game)) if bot.turn == turn => (bot.strategy, game),
+            _ => return,
+        };
+        if game.state.info.player_with_ball != Some(game.myself) {
+            return;
+        }
+
+        match self.resolve(strategy.target(game.passed_by)) {
+            Ok(to) => {
+                self.out.print(format!("🤖 Passing the ball to {}.", to));
+                self.server_tx.send(ToServer::PassBall(to));
+            }
+            // e.g. nobody else is playing, look again later
+            Err(_) => self.schedule_bot_turn(),
+        }
+    }
 
-                else => break ,
+    /// the player a pass command refers to
+    fn resolve(&self, target: Target) -> Result<Player, &'static str> {
+        let game = self.game.as_ref().ok_or(NOT_JOINED)?;
+        let others = game.state.players.iter().filter(|p| **p != game.myself);
+
+        match target {
+            Target::Player(player) if game.state.players.contains(&player) => Ok(player),
+            Target::Player(_) => Err("that player is not in the game"),
+            Target::Me => Ok(game.myself),
+            Target::Random => others
+                .copied()
+                .collect::<Vec<_>>()
+                .choose(&mut rand::thread_rng())
+                .copied()
+                .ok_or("there is nobody else to pass to"),
+            Target::Next => {
+                let after = others.clone().filter(|p| p.0 > game.myself.0).min_by_key(|p| p.0);
+                after
+                    .or_else(|| others.min_by_key(|p| p.0))
+                    .copied()
+                    .ok_or("there is nobody else to pass to")
+            }
+        }
+    }
+
+    /// refresh the terminal interface, if enabled
+    fn draw(&mut self) {
+        if let Output::Tui(tui) = &mut self.out {
+            let view = View {
+                server_addr: &self.server_addr,
+                connection: self.connection.to_string(),
+                myself: self.game.as_ref().map(|game| game.myself),
+                holder: self.game.as_ref().and_then(|game| game.state.info.player_with_ball),
+                players: self.game.as_ref().map_or(&[], |game| &game.state.players),
+                latencies: self.game.as_ref().map(|game| &game.latencies),
+                latency: self.latency,
             };
+            if let Err(err) = tui.draw(&view) {
+                warn!(?err, "failed to draw terminal interface");
+            }
         }
-        app_tx.send_with_urgency(Event::ConnectionDropped);
-    };
+    }
+}
+
+fn describe(reason: RejectReason) -> &'static str {
+    match reason {
+        RejectReason::NotHolder => "you don't have the ball",
+        RejectReason::UnknownTarget => "no such player in the game",
+        RejectReason::SelfPass => "you can't pass the ball to yourself",
+        RejectReason::RateLimited => "you are passing too quickly",
+        RejectReason::NotInGame => "you are still waiting to join the game",
+        RejectReason::GameNotRunning => "the game is not running",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameInfo;
+    use futures_util::FutureExt;
+
+    fn bot(strategy: Strategy, server: &EventQueue<ToServer>) -> ClientApp {
+        ClientApp {
+            event_queue: EventQueue::default(),
+            server_tx: server.sender().clone(),
+            game: None,
+            server_addr: String::new(),
+            connection: Connection::Connecting,
+            out: Output::Plain,
+            bot: Some(Bot {
+                strategy,
+                think_time: Duration::from_secs(60),
+                turn: 0,
+            }),
+            latency: None,
+        }
+    }
 
-    // spawn connection loop
-    tokio::spawn(connection_loop);
+    #[tokio::test]
+    async fn echo_bot_passes_on_when_its_passer_left() {
+        let mut server = EventQueue::default();
+        let mut app = bot(Strategy::Echo, &server);
+        let state = GameState {
+            info: GameInfo {
+                player_with_ball: Some(Player(3)),
+            },
+            players: vec![Player(1), Player(2), Player(3)],
+        };
+        app.on_server_message(ToClient::InitialState(Player(1), state));
+        app.on_server_message(ToClient::PassBall(Player(1), WhoPassed::Player));
+        app.on_server_message(ToClient::PlayerLeave(Player(3)));
 
-    server_tx
+        let turn = app.bot.as_ref().unwrap().turn;
+        app.on_bot_turn(turn);
+        match server.recv_async().now_or_never() {
+            Some(Some(ToServer::PassBall(to))) => assert_eq!(to, Player(2)),
+            _ => panic!("bot kept the ball"),
+        }
+    }
+
+    #[tokio::test]
+    async fn bot_tries_again_when_nobody_can_take_the_ball() {
+        let server = EventQueue::default();
+        let mut app = bot(Strategy::Next, &server);
+        let state = GameState {
+            info: GameInfo {
+                player_with_ball: Some(Player(1)),
+            },
+            players: vec![Player(1)],
+        };
+        app.on_server_message(ToClient::InitialState(Player(1), state));
+
+        app.on_bot_turn(0);
+        assert_eq!(app.bot.as_ref().unwrap().turn, 1, "no new turn scheduled");
+    }
 }
@@ -0,0 +1,228 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use futures_util::future::{self, Either};
+
+/// times other tasks get to run after every step of a simulated clock. The
+/// runtime also polls sockets in between, so messages in flight arrive.
+const SETTLE_YIELDS: usize = 256;
+
+/// source of time for event queue timers, client sessions and the server.
+///
+/// `System` follows the real clock. `Simulated` only moves when a test advances
+/// it, so timeouts can be fast-forwarded and every run sees the same timings.
+#[derive(Clone, Debug, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Simulated(SimulatedClock),
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Simulated(clock) => clock.now(),
+        }
+    }
+
+    /// random spread for recurring timers of up to `jitter`. None on a simulated
+    /// clock, so every run fires its timers at the same times.
+    pub fn timer_jitter(&self, jitter: Duration) -> Duration {
+        match self {
+            Clock::System => jitter,
+            Clock::Simulated(_) => Duration::from_secs(0),
+        }
+    }
+
+    /// wait until `deadline` has passed on this clock
+    pub async fn sleep_until(&self, deadline: Instant) {
+        match self {
+            Clock::System => tokio::time::delay_until(deadline.into()).await,
+            Clock::Simulated(clock) => clock.sleep_until(deadline).await,
+        }
+    }
+
+    /// run `future` to completion unless `duration` passes first
+    pub async fn timeout<F: Future>(&self, duration: Duration, future: F) -> Option<F::Output> {
+        let deadline = self.sleep_until(self.now() + duration);
+        futures_util::pin_mut!(future, deadline);
+        match future::select(future, deadline).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+/// virtual time, shared by every clone
+#[derive(Clone, Debug)]
+pub struct SimulatedClock {
+    inner: Arc<Mutex<Simulation>>,
+}
+
+#[derive(Debug)]
+struct Simulation {
+    now: Instant,
+    next_id: u64,
+    /// tasks sleeping until a deadline, in firing order
+    sleepers: BTreeMap<(Instant, u64), Waker>,
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Simulation {
+                now: Instant::now(),
+                next_id: 0,
+                sleepers: BTreeMap::new(),
+            })),
+        }
+    }
+}
+
+impl SimulatedClock {
+    pub fn now(&self) -> Instant { self.inner.lock().unwrap().now }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Sleep {
+            clock: self.clone(),
+            deadline,
+            key: None,
+        }
+    }
+
+    /// deadline of the earliest pending sleep
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let sim = self.inner.lock().unwrap();
+        sim.sleepers.keys().next().map(|(deadline, _)| *deadline)
+    }
+
+    /// move time forward by `by`, waking every sleep due on the way in order.
+    ///
+    /// After each step the woken tasks, and anything waiting on a socket, get to
+    /// run and schedule new timers before time moves on.
+    pub async fn advance(&self, by: Duration) {
+        let target = self.now() + by;
+        loop {
+            settle().await;
+            match self.next_deadline() {
+                Some(deadline) if deadline <= target => self.set_now(deadline),
+                _ => break,
+            }
+        }
+        self.set_now(target);
+        settle().await;
+    }
+
+    fn set_now(&self, now: Instant) {
+        let due = {
+            let mut sim = self.inner.lock().unwrap();
+            let now = sim.now.max(now);
+            sim.now = now;
+            let later = sim.sleepers.split_off(&(now, u64::MAX));
+            std::mem::replace(&mut sim.sleepers, later)
+        };
+        due.into_values().for_each(Waker::wake);
+    }
+}
+
+/// let other tasks run until things quiet down
+async fn settle() {
+    for _ in 0..SETTLE_YIELDS {
+        let _ = tokio::task::yield_now().await;
+    }
+}
+
+/// future of `SimulatedClock::sleep_until`
+struct Sleep {
+    clock: SimulatedClock,
+    deadline: Instant,
+    /// where our waker is registered while pending
+    key: Option<(Instant, u64)>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let inner = self.clock.inner.clone();
+        let mut sim = inner.lock().unwrap();
+        if sim.now >= self.deadline {
+            if let Some(key) = self.key.take() {
+                sim.sleepers.remove(&key);
+            }
+            return Poll::Ready(());
+        }
+
+        let key = match self.key {
+            Some(key) => key,
+            None => {
+                sim.next_id += 1;
+                (self.deadline, sim.next_id)
+            }
+        };
+        sim.sleepers.insert(key, cx.waker().clone());
+        self.key = Some(key);
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            if let Ok(mut sim) = self.clock.inner.lock() {
+                sim.sleepers.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn advance_wakes_sleepers_in_order() {
+        let clock = SimulatedClock::default();
+        let start = clock.now();
+        let (tx, rx) = flume::unbounded();
+        for secs in [3, 1, 2, 10] {
+            let clock = Clock::Simulated(clock.clone());
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                clock.sleep_until(start + Duration::from_secs(secs)).await;
+                tx.send((secs, clock.now() - start)).unwrap();
+            });
+        }
+
+        clock.advance(Duration::from_secs(5)).await;
+        let woken: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            woken,
+            vec![
+                (1, Duration::from_secs(1)),
+                (2, Duration::from_secs(2)),
+                (3, Duration::from_secs(3)),
+            ]
+        );
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+        assert_eq!(clock.next_deadline(), Some(start + Duration::from_secs(10)));
+    }
+
+    #[tokio::test]
+    async fn timeout_fires_on_simulated_time() {
+        let sim = SimulatedClock::default();
+        let clock = Clock::Simulated(sim.clone());
+        let never = future::pending::<()>();
+        let timeout = tokio::spawn(async move { clock.timeout(Duration::from_secs(60), never).await });
+
+        sim.advance(Duration::from_secs(60)).await;
+        assert_eq!(timeout.await.unwrap(), None);
+    }
+}
@@ -0,0 +1,183 @@
+use std::str::FromStr;
+
+use crate::game::Player;
+
+/// command names, in the order `help` lists them
+pub const COMMANDS: &[&str] = &["help", "pass", "players", "whoami", "status", "quit"];
+
+pub const HELP: &str = "\
+commands:
+  help                             show this help
+  pass <id|me|random|next>         pass the ball, e.g. `pass 3` or `pass #3`
+  players                          list the players in the game
+  whoami                           show your player ID
+  status                           show the connection and game state
+  quit                             leave the game";
+
+/// a line typed by the player
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Pass(Target),
+    Players,
+    WhoAmI,
+    Status,
+    Quit,
+}
+
+/// who to pass the ball to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Player(Player),
+    /// yourself
+    Me,
+    /// any other player
+    Random,
+    /// the player with the next higher ID, wrapping around
+    Next,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+    #[error("type a command, or `help` to list them")]
+    Empty,
+
+    #[error("unknown command `{0}`, type `help` to list them")]
+    UnknownCommand(String),
+
+    #[error("missing argument, usage: {0}")]
+    MissingArgument(&'static str),
+
+    #[error("`{0}` takes no arguments")]
+    UnexpectedArgument(&'static str),
+
+    #[error("too many arguments, usage: {0}")]
+    TooManyArguments(&'static str),
+
+    #[error("invalid player `{0}`, expected an ID like `3` or `#3`, `me`, `random` or `next`")]
+    InvalidTarget(String),
+}
+
+impl FromStr for Target {
+    type Err = ParseError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg.to_ascii_lowercase().as_str() {
+            "me" => Ok(Target::Me),
+            "random" => Ok(Target::Random),
+            "next" => Ok(Target::Next),
+            id => id
+                .strip_prefix('#')
+                .unwrap_or(id)
+                .parse()
+                .map(|id| Target::Player(Player(id)))
+                .map_err(|_| ParseError::InvalidTarget(arg.to_owned())),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or(ParseError::Empty)?.to_ascii_lowercase();
+        let args: Vec<&str> = words.collect();
+
+        let no_args = |name, command| {
+            if args.is_empty() {
+                Ok(command)
+            } else {
+                Err(ParseError::UnexpectedArgument(name))
+            }
+        };
+
+        match name.as_str() {
+            "help" | "?" => no_args("help", Command::Help),
+            "pass" | "p" => {
+                const USAGE: &str = "pass <id|me|random|next>";
+                match args[..] {
+                    [] => Err(ParseError::MissingArgument(USAGE)),
+                    [target] => target.parse().map(Command::Pass),
+                    _ => Err(ParseError::TooManyArguments(USAGE)),
+                }
+            }
+            "players" | "ls" => no_args("players", Command::Players),
+            "whoami" => no_args("whoami", Command::WhoAmI),
+            "status" => no_args("status", Command::Status),
+            "quit" | "exit" => no_args("quit", Command::Quit),
+            _ => Err(ParseError::UnknownCommand(name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Command, ParseError> { line.parse() }
+
+    #[test]
+    fn parses_commands_without_arguments() {
+        assert_eq!(parse("help"), Ok(Command::Help));
+        assert_eq!(parse("?"), Ok(Command::Help));
+        assert_eq!(parse("players"), Ok(Command::Players));
+        assert_eq!(parse("whoami"), Ok(Command::WhoAmI));
+        assert_eq!(parse("status"), Ok(Command::Status));
+        assert_eq!(parse("quit"), Ok(Command::Quit));
+        assert_eq!(parse("exit"), Ok(Command::Quit));
+    }
+
+    #[test]
+    fn ignores_case_and_surrounding_whitespace() {
+        assert_eq!(parse("  QUIT  "), Ok(Command::Quit));
+        assert_eq!(parse("Pass   Next"), Ok(Command::Pass(Target::Next)));
+    }
+
+    #[test]
+    fn parses_pass_targets() {
+        assert_eq!(parse("pass 3"), Ok(Command::Pass(Target::Player(Player(3)))));
+        assert_eq!(parse("pass #12"), Ok(Command::Pass(Target::Player(Player(12)))));
+        assert_eq!(parse("p 1"), Ok(Command::Pass(Target::Player(Player(1)))));
+        assert_eq!(parse("pass me"), Ok(Command::Pass(Target::Me)));
+        assert_eq!(parse("pass random"), Ok(Command::Pass(Target::Random)));
+        assert_eq!(parse("pass next"), Ok(Command::Pass(Target::Next)));
+    }
+
+    #[test]
+    fn rejects_empty_input() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("   "), Err(ParseError::Empty));
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert_eq!(parse("throw 3"), Err(ParseError::UnknownCommand("throw".into())));
+    }
+
+    #[test]
+    fn rejects_pass_without_target() {
+        assert!(matches!(parse("pass"), Err(ParseError::MissingArgument(_))));
+    }
+
+    #[test]
+    fn rejects_invalid_targets() {
+        assert_eq!(parse("pass bob"), Err(ParseError::InvalidTarget("bob".into())));
+        assert_eq!(parse("pass -1"), Err(ParseError::InvalidTarget("-1".into())));
+        assert_eq!(parse("pass #"), Err(ParseError::InvalidTarget("#".into())));
+    }
+
+    #[test]
+    fn rejects_extra_arguments() {
+        assert!(matches!(parse("pass 1 2"), Err(ParseError::TooManyArguments(_))));
+        assert_eq!(parse("quit now"), Err(ParseError::UnexpectedArgument("quit")));
+    }
+
+    #[test]
+    fn every_listed_command_parses() {
+        for name in COMMANDS {
+            let line = if *name == "pass" { "pass 1".to_owned() } else { name.to_string() };
+            assert!(parse(&line).is_ok(), "`{}` should parse", line);
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    convert::TryFrom,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+pub type Result<T> = std::result::Result<T, Error>;
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("could not read config file {0}")]
+    IO(PathBuf, #[source] std::io::Error),
+
+    #[error("could not parse config file {0}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+
+    #[error("invalid config: {0}")]
+    Invalid(String),
+}
+
+/// server configuration, read from a TOML file with `--config`.
+///
+/// Every field is optional in the file; missing values fall back to the
+/// defaults below.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// addresses the server listens on, all feeding into the same game
+    pub bind: Vec<BindAddr>,
+    /// port used by bind addresses that don't specify one
+    pub port: u16,
+    /// maximum number of players in the game, unlimited if unset.
+    /// Clients connecting beyond it wait in a queue for a free spot.
+    pub max_players: Option<usize>,
+    /// maximum number of clients in the waiting queue, unlimited if unset
+    pub max_queued: Option<usize>,
+    /// ban list file, reloaded on SIGHUP
+    pub bans: Option<PathBuf>,
+    /// log event queue metrics this often, never if unset
+    #[serde(with = "humantime_serde")]
+    pub metrics_interval: Option<Duration>,
+    pub connections: ConnectionLimits,
+    pub rate_limit: RateLimitConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub tcp: TcpConfig,
+    pub shutdown: ShutdownConfig,
+    pub game: GameConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec![BindAddr::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST))],
+            port: 4565,
+            max_players: None,
+            max_queued: None,
+            bans: None,
+            metrics_interval: None,
+            connections: ConnectionLimits::default(),
+            rate_limit: RateLimitConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            tcp: TcpConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            game: GameConfig::default(),
+        }
+    }
+}
+
+/// a listen address: either a bare IP (`::`, `0.0.0.0`) using the configured port,
+/// or a full socket address (`[::1]:4000`, `127.0.0.1:4000`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum BindAddr {
+    Ip(IpAddr),
+    Socket(SocketAddr),
+}
+
+impl BindAddr {
+    pub fn with_default_port(self, port: u16) -> SocketAddr {
+        match self {
+            BindAddr::Ip(ip) => SocketAddr::new(ip, port),
+            BindAddr::Socket(addr) => addr,
+        }
+    }
+}
+
+impl FromStr for BindAddr {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        value
+            .parse()
+            .map(BindAddr::Socket)
+            .or_else(|_| value.parse().map(BindAddr::Ip))
+            .map_err(|_| format!("invalid bind address `{}`", value))
+    }
+}
+
+impl TryFrom<String> for BindAddr {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> { value.parse() }
+}
+
+impl Display for BindAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindAddr::Ip(ip) => write!(f, "{}", ip),
+            BindAddr::Socket(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+/// limits applied per client IP address before a connection joins the game
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimits {
+    /// maximum number of concurrent connections from one address, unlimited if unset
+    pub max_per_ip: Option<usize>,
+    /// joins per second allowed from one address, unlimited if unset
+    pub join_rate: Option<f64>,
+    /// number of joins from one address allowed in a quick burst
+    pub join_burst: u32,
+    /// messages of each priority waiting to be sent to one client, so up to three
+    /// times as many in total. Clients that fall further behind, e.g. because they
+    /// stopped reading, are disconnected.
+    pub send_queue: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_per_ip: None,
+            join_rate: None,
+            join_burst: 5,
+            send_queue: 1024,
+        }
+    }
+}
+
+/// limits on the messages a single client session may send
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// limit on ball passes, unlimited if unset
+    pub pass_ball: Option<RateLimit>,
+    /// limit on heartbeats, unlimited if unset
+    pub heartbeat: Option<RateLimit>,
+    /// limit on latency pings and the answers to server pings, unlimited if unset
+    pub ping: Option<RateLimit>,
+    /// number of throttled messages after which the client is kicked
+    pub max_violations: u32,
+    /// forget a client's throttled messages after this long without one
+    #[serde(with = "humantime_serde")]
+    pub forgive_after: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            pass_ball: Some(RateLimit {
+                rate: 5.0,
+                burst: 10,
+            }),
+            heartbeat: Some(RateLimit {
+                rate: 2.0,
+                burst: 5,
+            }),
+            ping: Some(RateLimit {
+                rate: 2.0,
+                burst: 5,
+            }),
+            max_violations: 20,
+            forgive_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// token bucket parameters
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// messages per second
+    pub rate: f64,
+    /// messages allowed in a quick burst
+    pub burst: u32,
+}
+
+/// spare rate a keepalive limit needs over what clients send on average, as
+/// jittered heartbeats and network delays bunch messages up
+const KEEPALIVE_HEADROOM: f64 = 1.25;
+
+impl RateLimit {
+    /// check that clients sending `per_sec` messages on average stay within the limit
+    fn fits(&self, name: &str, per_sec: f64) -> Result<()> {
+        if self.rate < per_sec * KEEPALIVE_HEADROOM {
+            return Err(Error::Invalid(format!(
+                "{} allows {}/s, but with these heartbeat settings clients send {:.1}/s. \
+                 Raise it to at least {:.1}/s or lengthen the intervals",
+                name,
+                self.rate,
+                per_sec,
+                per_sec * KEEPALIVE_HEADROOM
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate(&self, name: &str) -> Result<()> {
+        if !(self.rate > 0.0 && self.rate.is_finite()) || self.burst == 0 {
+            return Err(Error::Invalid(format!(
+                "{} needs a positive rate and a burst of at least 1",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    /// how often the server and clients send heartbeats, announced to clients on connect
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// disconnect clients that have not sent a heartbeat for this long.
+    /// Clients give up on the server after the same time without messages.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    /// how often to ping clients to measure their latency
+    #[serde(with = "humantime_serde")]
+    pub ping_interval: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+            ping_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpConfig {
+    /// TCP keepalive set on every accepted socket
+    #[serde(with = "humantime_serde")]
+    pub keepalive: Duration,
+    /// kernel send buffer of every accepted socket in bytes, the system default if unset.
+    /// With a smaller buffer, clients that stop reading fill their send queue sooner.
+    pub send_buffer: Option<usize>,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            keepalive: Duration::from_secs(1),
+            send_buffer: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// time between the shutdown announcement and disconnecting players, in whole seconds
+    #[serde(with = "humantime_serde")]
+    pub countdown: Duration,
+    /// how long to wait for sessions to deliver their disconnect message and close
+    #[serde(with = "humantime_serde")]
+    pub flush_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            countdown: Duration::from_secs(5),
+            flush_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    pub mode: GameMode,
+    /// how long the ball may be held in `hot-potato` mode
+    #[serde(with = "humantime_serde")]
+    pub hold_limit: Duration,
+    pub rules: GameRules,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            mode: GameMode::Classic,
+            hold_limit: Duration::from_secs(10),
+            rules: GameRules::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GameMode {
+    /// players hold the ball for as long as they like
+    Classic,
+    /// the ball is passed on automatically when held for too long
+    HotPotato,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameRules {
+    /// whether the player holding the ball may pass it to themselves
+    pub allow_self_pass: bool,
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            allow_self_pass: true,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// read config from a TOML file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|err| Error::IO(path.to_owned(), err))?;
+
+        toml::from_str(&contents).map_err(|err| Error::Parse(path.to_owned(), err))
+    }
+
+    /// socket addresses to listen on, without duplicates
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs: Vec<SocketAddr> = Vec::with_capacity(self.bind.len());
+        for addr in self.bind.iter().map(|bind| bind.with_default_port(self.port)) {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        addrs
+    }
+
+    /// check values that parse fine but make no sense to run with
+    pub fn validate(&self) -> Result<()> {
+        if self.bind.is_empty() {
+            return Err(Error::Invalid("at least one bind address is required".into()));
+        }
+
+        if self.max_players == Some(0) {
+            return Err(Error::Invalid("max_players must be at least 1".into()));
+        }
+
+        if self.connections.max_per_ip == Some(0) {
+            return Err(Error::Invalid("connections.max_per_ip must be at least 1".into()));
+        }
+
+        if let Some(rate) = self.connections.join_rate {
+            if !(rate > 0.0 && rate.is_finite()) {
+                return Err(Error::Invalid("connections.join_rate must be positive".into()));
+            }
+        }
+
+        if self.connections.join_burst == 0 {
+            return Err(Error::Invalid("connections.join_burst must be at least 1".into()));
+        }
+
+        if self.metrics_interval == Some(Duration::from_secs(0)) {
+            return Err(Error::Invalid("metrics_interval must be non-zero".into()));
+        }
+
+        if self.connections.send_queue == 0 {
+            return Err(Error::Invalid("connections.send_queue must be at least 1".into()));
+        }
+
+        if let Some(limit) = &self.rate_limit.pass_ball {
+            limit.validate("rate_limit.pass_ball")?;
+        }
+
+        if let Some(limit) = &self.rate_limit.heartbeat {
+            limit.validate("rate_limit.heartbeat")?;
+        }
+
+        if let Some(limit) = &self.rate_limit.ping {
+            limit.validate("rate_limit.ping")?;
+        }
+
+        if self.rate_limit.max_violations == 0 {
+            return Err(Error::Invalid("rate_limit.max_violations must be at least 1".into()));
+        }
+
+        if self.rate_limit.forgive_after == Duration::from_secs(0) {
+            return Err(Error::Invalid("rate_limit.forgive_after must be non-zero".into()));
+        }
+
+        if self.heartbeat.interval == Duration::from_secs(0) {
+            return Err(Error::Invalid("heartbeat.interval must be non-zero".into()));
+        }
+
+        if self.heartbeat.timeout <= self.heartbeat.interval {
+            return Err(Error::Invalid(
+                "heartbeat.timeout must be longer than heartbeat.interval".into(),
+            ));
+        }
+
+        if self.heartbeat.ping_interval == Duration::from_secs(0) {
+            return Err(Error::Invalid("heartbeat.ping_interval must be non-zero".into()));
+        }
+
+        // clients send a heartbeat and a ping every interval and answer every server ping
+        let heartbeats = 1.0 / self.heartbeat.interval.as_secs_f64();
+        let pongs = 1.0 / self.heartbeat.ping_interval.as_secs_f64();
+        if let Some(limit) = &self.rate_limit.heartbeat {
+            limit.fits("rate_limit.heartbeat", heartbeats)?;
+        }
+        if let Some(limit) = &self.rate_limit.ping {
+            limit.fits("rate_limit.ping", heartbeats + pongs)?;
+        }
+
+        // the countdown ticks once a second, anything in between would be rounded down
+        if self.shutdown.countdown.subsec_nanos() != 0 {
+            return Err(Error::Invalid(
+                "shutdown.countdown must be whole seconds, or 0s to shut down right away".into(),
+            ));
+        }
+
+        if self.shutdown.flush_timeout == Duration::from_secs(0) {
+            return Err(Error::Invalid("shutdown.flush_timeout must be non-zero".into()));
+        }
+
+        let hot_potato = self.game.mode == GameMode::HotPotato;
+        if hot_potato && self.game.hold_limit == Duration::from_secs(0) {
+            return Err(Error::Invalid("game.hold_limit must be non-zero".into()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keepalive_limits_must_fit_the_heartbeat_intervals() {
+        let mut config = ServerConfig::default();
+        assert!(config.validate().is_ok());
+
+        config.heartbeat.interval = Duration::from_millis(500);
+        config.heartbeat.timeout = Duration::from_secs(2);
+        assert!(config.validate().is_err(), "2 heartbeats/s fill the default limit");
+
+        config.rate_limit.heartbeat = Some(RateLimit {
+            rate: 2.5,
+            burst: 5,
+        });
+        assert!(config.validate().is_err(), "pings and pongs don't fit either");
+
+        config.rate_limit.ping = None;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn shutdown_countdown_is_whole_seconds() {
+        let mut config = ServerConfig::default();
+        for (countdown, valid) in [(0, true), (500, false), (1000, true), (1500, false)] {
+            config.shutdown.countdown = Duration::from_millis(countdown);
+            assert_eq!(config.validate().is_ok(), valid, "{}ms", countdown);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::time::{Duration, Instant};
+
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use crate::{
+    events::{EventQueue, EventSender, TimerHandle},
+    latency::Pinger,
+    message::{ToClient, ToServer},
+    utils,
+};
+
+/// how to reach the server and what to do when the connection is lost
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    pub server_addr: String,
+    /// reconnect with exponential backoff when the connection is lost
+    pub reconnect: bool,
+    /// give up reconnecting after this many failed attempts in a row, retries forever if unset
+    pub max_retries: Option<u32>,
+    /// consider the connection dead after this long without hearing from the server,
+    /// instead of the timeout the server announces
+    pub server_timeout: Option<Duration>,
+    /// seed for the reconnect jitter, so a run can be replayed exactly. Random if unset.
+    pub seed: Option<u64>,
+}
+
+impl ConnectOptions {
+    pub fn new(server_addr: impl Into<String>) -> Self {
+        Self {
+            server_addr: server_addr.into(),
+            ..Self::default()
+        }
+    }
+}
+
+/// what happens on the connection to the server
+#[derive(Clone, Debug)]
+pub enum ConnectionEvent {
+    /// connected to the server, the game state follows
+    Connected,
+    Message(ToClient),
+    /// the connection was lost, trying again after `delay`
+    Reconnecting {
+        reason: String,
+        attempt: u32,
+        delay: Duration,
+    },
+    /// the connection was lost for good
+    Dropped(String),
+    /// new smoothed round trip time to the server
+    Latency(Duration),
+}
+
+/// headless client: a connection to the server that answers pings, sends
+/// heartbeats and reconnects on its own, leaving the game to the caller
+pub struct Client {
+    events: EventQueue<ConnectionEvent>,
+    server_tx: EventSender<ToServer>,
+}
+
+impl Client {
+    /// start connecting in the background, progress is reported through `recv`
+    pub fn connect(options: ConnectOptions) -> Self {
+        let events = EventQueue::default();
+        let server_tx = connect_with(options, events.sender().clone());
+        Self { events, server_tx }
+    }
+
+    pub fn sender(&self) -> &EventSender<ToServer> { &self.server_tx }
+
+    pub fn send(&self, msg: ToServer) { self.server_tx.send(msg); }
+
+    /// leave the game and close the connection
+    pub fn leave(&self) { self.server_tx.send_with_urgency(ToServer::Leave) }
+
+    /// next event on the connection. Nothing follows `ConnectionEvent::Dropped`.
+    pub async fn recv(&mut self) -> Option<ConnectionEvent> { self.events.recv_async().await }
+}
+
+/// heartbeat settings used until the server announces its own
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// first delay before reconnecting, doubled on every failed attempt
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// exponential backoff between reconnect attempts.
+///
+/// Each delay is picked at random between half and all of the exponential
+/// value, so clients dropped at the same time don't reconnect in lockstep.
+struct Backoff {
+    attempt: u32,
+    max_retries: Option<u32>,
+    rng: StdRng,
+}
+
+impl Backoff {
+    fn new(max_retries: Option<u32>, seed: Option<u64>) -> Self {
+        Self {
+            attempt: 0,
+            max_retries,
+            rng: seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+        }
+    }
+
+    fn reset(&mut self) { self.attempt = 0; }
+
+    /// delay before the next attempt, `None` once out of retries
+    fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_retries.is_some_and(|max| self.attempt >= max) {
+            return None;
+        }
+
+        let exp = BACKOFF_BASE
+            .checked_mul(1 << self.attempt.min(16))
+            .map_or(BACKOFF_MAX, |delay| delay.min(BACKOFF_MAX));
+        self.attempt += 1;
+
+        let half = exp / 2;
+        Some(half + half.mul_f64(self.rng.gen::<f64>()))
+    }
+}
+
+/// why a connection loop ended
+enum Closed {
+    /// the player left the game
+    Left,
+    Dropped(String),
+    /// the server removed us for good, reconnecting won't help
+    Kicked(String),
+}
+
+/// connect to the server in a background task, reporting to `app_tx`.
+///
+/// Returns the sender for messages to the server; sending `ToServer::Leave`
+/// closes the connection for good.
+pub fn connect_with<E>(options: ConnectOptions, app_tx: EventSender<E>) -> EventSender<ToServer>
+where
+    E: From<ConnectionEvent> + Send + 'static,
+{
+    let ConnectOptions {
+        server_addr,
+        reconnect,
+        max_retries,
+        server_timeout,
+        seed,
+    } = options;
+    let reconnect = reconnect.then(|| Backoff::new(max_retries, seed));
+
+    let mut server_msg_queue: EventQueue<ToServer> = EventQueue::default();
+    let server_tx = server_msg_queue.sender().clone();
+
+    let connection_task = async move {
+        let mut backoff = reconnect;
+        loop {
+            // start connection to server
+            let reason = match TcpStream::connect(&server_addr).await {
+                Ok(stream) => {
+                    if !discard_queued(&mut server_msg_queue) {
+                        break;
+                    }
+                    if let Some(backoff) = &mut backoff {
+                        backoff.reset();
+                    }
+                    app_tx.send(ConnectionEvent::Connected.into());
+
+                    let socket = utils::frame_socket::<ToClient, ToServer>(stream);
+                    let closed =
+                        connection_loop(socket, server_timeout, &mut server_msg_queue, &app_tx);
+                    match closed.await {
+                        Closed::Left => break,
+                        Closed::Dropped(reason) => reason,
+                        Closed::Kicked(reason) => {
+                            app_tx.send(ConnectionEvent::Dropped(reason).into());
+                            break;
+                        }
+                    }
+                }
+                Err(err) => format!("could not connect to {}: {}", server_addr, err),
+            };
+
+            let retry = backoff
+                .as_mut()
+                .and_then(|backoff| Some((backoff.next_delay()?, backoff.attempt)));
+            let (delay, attempt) = match retry {
+                Some(retry) => retry,
+                None => {
+                    // queued behind the server messages so the player still sees why they were dropped
+                    app_tx.send(ConnectionEvent::Dropped(reason).into());
+                    break;
+                }
+            };
+            debug!(%reason, attempt, ?delay, "reconnecting");
+            app_tx.send(
+                ConnectionEvent::Reconnecting {
+                    reason,
+                    attempt,
+                    delay,
+                }
+                .into(),
+            );
+
+            // anything the player does while disconnected is dropped, except leaving
+            let wait = tokio::time::delay_for(delay).fuse();
+            futures_util::pin_mut!(wait);
+            loop {
+                futures_util::select! {
+                    _ = wait => break,
+                    msg = server_msg_queue.recv_async().fuse() => {
+                        if let Some(ToServer::Leave) | None = msg {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    // spawn connection task
+    tokio::spawn(connection_task);
+
+    server_tx
+}
+
+/// drop messages queued for an earlier connection, e.g. pongs to its pings and
+/// passes for a game we are no longer part of. False if the player left meanwhile.
+fn discard_queued(queue: &mut EventQueue<ToServer>) -> bool {
+    while let Some(Some(msg)) = queue.recv_async().now_or_never() {
+        if let ToServer::Leave = msg {
+            return false;
+        }
+    }
+    true
+}
+
+struct HeartbeatTick;
+
+/// heartbeats every `interval`, jittered a little so clients reconnecting
+/// together don't keep hitting the server in the same instant
+fn start_heartbeat(ticks: &EventQueue<HeartbeatTick>, interval: Duration) -> TimerHandle<HeartbeatTick> {
+    ticks
+        .sender()
+        .send_every_with_jitter(|| HeartbeatTick, interval, interval / 10)
+}
+
+async fn connection_loop<E>(
+    socket: (utils::MessageReader<ToClient>, utils::MessageWriter<ToServer>),
+    server_timeout: Option<Duration>,
+    server_msg_queue: &mut EventQueue<ToServer>,
+    app_tx: &EventSender<E>,
+) -> Closed
+where
+    E: From<ConnectionEvent> + Send + 'static,
+{
+    let (mut server_to_client, mut client_to_server) = socket;
+    let mut pinger = Pinger::default();
+
+    // send heartbeats otherwise server will disconnect, at the interval it announces
+    let mut ticks: EventQueue<HeartbeatTick> = EventQueue::default();
+    let mut heartbeat = start_heartbeat(&ticks, DEFAULT_HEARTBEAT_INTERVAL);
+    let mut timeout = server_timeout.unwrap_or(DEFAULT_HEARTBEAT_TIMEOUT);
+    let mut last_heard = Instant::now();
+    let closed = loop {
+        let silence = tokio::time::delay_until((last_heard + timeout).into());
+
+        tokio::select! {
+            _ = silence => {
+                let silent_for = humantime::format_duration(timeout);
+                break Closed::Dropped(format!("no response from server for {}", silent_for));
+            }
+
+            Some(HeartbeatTick) = ticks.recv_async() => {
+                let (nonce, sent_at) = pinger.ping();
+                server_msg_queue.sender().send(ToServer::Heartbeat);
+                server_msg_queue.sender().send(ToServer::Ping(nonce, sent_at));
+            }
+
+            Some(to_server_msg) = server_msg_queue.recv_async() => {
+                if let ToServer::Leave = to_server_msg {
+                    let _ = client_to_server.send(to_server_msg).await;
+                    break Closed::Left;
+                } else if let Err(err) = client_to_server.send(to_server_msg).await {
+                    break Closed::Dropped(format!("connection error: {}", err));
+                }
+            }
+
+            server_msg = server_to_client.next() => {
+                last_heard = Instant::now();
+                match server_msg {
+                    Some(Ok(ToClient::Hello { heartbeat_interval, heartbeat_timeout })) => {
+                        debug!(?heartbeat_interval, ?heartbeat_timeout, "heartbeat settings received");
+                        heartbeat.cancel();
+                        heartbeat = start_heartbeat(&ticks, heartbeat_interval);
+                        timeout = server_timeout.unwrap_or(heartbeat_timeout);
+                    }
+
+                    Some(Ok(ToClient::Heartbeat)) => {}
+
+                    Some(Ok(ToClient::Disconnect(reason))) => {
+                        app_tx.send(ConnectionEvent::Message(ToClient::Disconnect(reason)).into());
+                        break Closed::Dropped("disconnected by server".into())
+                    },
+
+                    Some(Ok(ToClient::Kicked(reason))) => {
+                        app_tx.send(ConnectionEvent::Message(ToClient::Kicked(reason)).into());
+                        break Closed::Kicked("removed by server".into())
+                    },
+
+                    Some(Ok(ToClient::Ping(nonce, sent_at))) => {
+                        server_msg_queue.sender().send(ToServer::Pong(nonce, sent_at));
+                    }
+
+                    Some(Ok(ToClient::Pong(nonce, _))) => {
+                        if let Some(rtt) = pinger.on_pong(nonce) {
+                            app_tx.send(ConnectionEvent::Latency(rtt).into());
+                        }
+                    }
+
+                    Some(Ok(msg)) => {
+                        app_tx.send(ConnectionEvent::Message(msg).into());
+                    }
+
+                    Some(Err(err)) => {
+                        warn!(?err, "failed to decode server message");
+                        break Closed::Dropped("invalid message from server".into())
+                    }
+
+                    None => break Closed::Dropped("server closed the connection".into()),
+                };
+            }
+        };
+    };
+    debug!("server connection closed");
+    closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Player;
+
+    #[test]
+    fn seeded_backoff_is_repeatable() {
+        let delays = |seed| {
+            let mut backoff = Backoff::new(Some(8), Some(seed));
+            std::iter::from_fn(|| backoff.next_delay()).collect::<Vec<_>>()
+        };
+
+        let first = delays(7);
+        assert_eq!(first, delays(7));
+        assert_eq!(first.len(), 8);
+        for (attempt, delay) in first.iter().enumerate() {
+            let exp = (BACKOFF_BASE * (1 << attempt)).min(BACKOFF_MAX);
+            assert!(*delay >= exp / 2 && *delay <= exp, "{:?} out of range", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnecting_discards_messages_for_the_old_connection() {
+        let mut queue = EventQueue::default();
+        queue.sender().send(ToServer::Pong(1, 2));
+        queue.sender().send(ToServer::PassBall(Player(3)));
+        assert!(discard_queued(&mut queue));
+        assert!(queue.recv_async().now_or_never().is_none());
+
+        queue.sender().send(ToServer::Heartbeat);
+        queue.sender().send(ToServer::Leave);
+        assert!(!discard_queued(&mut queue));
+    }
+}
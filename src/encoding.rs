@@ -2,10 +2,10 @@ use std::convert::TryFrom;
 use std::marker::PhantomData;
 
 use byteorder::ReadBytesExt;
-use bytes::{Buf, BufMut, BytesMut};
-use log::error;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 use tokio_util::codec::{Decoder, Encoder};
+use tracing::error;
 
 pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug, thiserror::Error)]
@@ -32,28 +32,59 @@ impl<T> NetworkMessage<T> {
     pub fn new() -> Self { Self { __: PhantomData } }
 }
 
+/// a `T` encoded once, header included, so the same bytes can be written to
+/// many sockets speaking `T`. Clones share the buffer.
+#[derive(Clone, Debug)]
+pub struct Frame<T> {
+    bytes: Bytes,
+    __: PhantomData<T>,
+}
+
+impl<T: Serialize> Frame<T> {
+    pub fn encode(msg: &T) -> Result<Self> {
+        let mut buf = BytesMut::new();
+        write_frame(msg, &mut buf)?;
+        Ok(Frame {
+            bytes: buf.freeze(),
+            __: PhantomData,
+        })
+    }
+}
+
+/// serialize `msg` and write it with its length header to `buf`
+fn write_frame<T: Serialize>(msg: &T, buf: &mut BytesMut) -> Result<()> {
+    let msg = bincode::serialize(msg)?;
+    let msg_len = msg.len();
+
+    // reserve space for bytelen
+    if u16::try_from(msg_len).is_err() {
+        error!(size = msg_len, "payload size can't be larger than 16 bits");
+        Err(Error::LargePayload)
+    } else {
+        buf.reserve(2 + msg_len);
+
+        buf.put_u16(msg_len as u16);
+        buf.put(&msg[..]);
+
+        Ok(())
+    }
+}
+
 impl<T> Encoder<T> for NetworkMessage<T>
 where
     T: Serialize,
 {
     type Error = Error;
 
-    fn encode(&mut self, msg: T, buf: &mut BytesMut) -> Result<()> {
-        let msg = bincode::serialize(&msg)?;
-        let msg_len = msg.len();
-
-        // reserve space for bytelen
-        if u16::try_from(msg_len).is_err() {
-            error!("payload size can't be larger than 16 bytes");
-            Err(Error::LargePayload)
-        } else {
-            buf.reserve(2 + msg_len);
+    fn encode(&mut self, msg: T, buf: &mut BytesMut) -> Result<()> { write_frame(&msg, buf) }
+}
 
-            buf.put_u16(msg_len as u16);
-            buf.put(&msg[..]);
+impl<T> Encoder<Frame<T>> for NetworkMessage<T> {
+    type Error = Error;
 
-            Ok(())
-        }
+    fn encode(&mut self, frame: Frame<T>, buf: &mut BytesMut) -> Result<()> {
+        buf.extend_from_slice(&frame.bytes);
+        Ok(())
     }
 }
 
@@ -90,3 +121,24 @@ where
         Ok(Some(bincode::deserialize(data)?))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{game::Player, message::ToClient};
+
+    #[test]
+    fn frames_are_written_like_messages() {
+        let msg = ToClient::PlayerJoin(Player(7));
+        let mut codec = NetworkMessage::<ToClient>::new();
+        let (mut encoded, mut framed) = (BytesMut::new(), BytesMut::new());
+        codec.encode(msg.clone(), &mut encoded).unwrap();
+        codec.encode(Frame::encode(&msg).unwrap(), &mut framed).unwrap();
+        assert_eq!(encoded, framed);
+
+        match codec.decode(&mut framed).unwrap() {
+            Some(ToClient::PlayerJoin(Player(7))) => {}
+            other => panic!("decoded {:?}", other),
+        }
+    }
+}
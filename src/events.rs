@@ -1,19 +1,220 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    task::{Context, Poll},
     time::{Duration, Instant},
 };
 
-use flume::{Receiver, Sender};
-use futures_util::{select, FutureExt};
+use flume::{r#async::RecvStream, Receiver, Sender, TrySendError};
+use futures_util::{future, select, FutureExt, Stream, StreamExt};
+use rand::Rng;
+
+use crate::clock::Clock;
+
+/// how soon a queued event is received, higher priorities first.
+/// Due timers are received right after urgent events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// never limited by a bounded queue
+    Urgent,
+    High,
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Urgent,
+        Priority::High,
+        Priority::Normal,
+        Priority::Low,
+    ];
+
+    fn index(self) -> usize { self as usize }
+}
 
 /// simple generic event queue
 /// inspired by message.io EventReceiver<E>
-pub struct EventQueue<E> {
+///
+/// Besides `recv_async` it is a `Stream` of its events, which never ends as the
+/// queue holds a sender itself.
+pub struct EventQueue<E: 'static> {
     sender: EventSender<E>,
-    recv: Receiver<E>,
-    urgent_recv: Receiver<E>,
-    timer_recv: Receiver<(Instant, E)>,
-    timers: BTreeMap<Instant, E>,
+    /// one per `Priority`, in order
+    lanes: Vec<Lane<E>>,
+    timer_recv: Receiver<TimerCommand<E>>,
+    timer_stream: RecvStream<'static, TimerCommand<E>>,
+    /// pending timers in firing order, several may share a deadline
+    timers: BTreeMap<(Instant, u64), Timer<E>>,
+    /// deadline of every pending timer by ID
+    deadlines: HashMap<u64, Instant>,
+    /// wait for the earliest timer, with its deadline
+    sleep: Option<(Instant, Sleep)>,
+    /// the lane receivers `Overflow::DropOldest` senders drop from. The senders
+    /// only hold a weak handle, so the channels close along with the queue.
+    _overflow_recv: Option<Arc<Vec<Receiver<Queued<E>>>>>,
+    clock: Clock,
+}
+
+type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// events and timers are only moved around, never pinned
+impl<E: 'static> Unpin for EventQueue<E> {}
+
+/// an event with the time it was sent
+struct Queued<E> {
+    event: E,
+    sent_at: Instant,
+}
+
+/// events of one priority
+struct Lane<E: 'static> {
+    recv: Receiver<Queued<E>>,
+    stream: RecvStream<'static, Queued<E>>,
+    /// taken off the channel to see its age, received before the rest
+    head: Option<Queued<E>>,
+}
+
+impl<E: 'static> Lane<E> {
+    fn new(recv: Receiver<Queued<E>>) -> Self {
+        Self {
+            stream: recv.clone().into_stream(),
+            recv,
+            head: None,
+        }
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Option<E> {
+        if let Some(queued) = self.head.take() {
+            return Some(queued.event);
+        }
+        match self.stream.poll_next_unpin(cx) {
+            Poll::Ready(Some(queued)) => Some(queued.event),
+            _ => None,
+        }
+    }
+
+    /// the oldest event of the lane
+    fn peek(&mut self) -> Option<&Queued<E>> {
+        if self.head.is_none() {
+            self.head = self.recv.try_recv().ok();
+        }
+        self.head.as_ref()
+    }
+
+    fn len(&self) -> usize { self.recv.len() + self.head.is_some() as usize }
+}
+
+/// what an `EventQueue` holds at one point in time
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueueMetrics {
+    /// events waiting to be received, indexed by `Priority`
+    pub depths: [usize; 4],
+    pub pending_timers: usize,
+    /// how long the oldest waiting event has been queued
+    pub oldest: Option<Duration>,
+}
+
+impl QueueMetrics {
+    pub fn depth(&self, priority: Priority) -> usize { self.depths[priority.index()] }
+
+    /// events waiting to be received in total
+    pub fn queued(&self) -> usize { self.depths.iter().sum() }
+}
+
+/// changes to the timers, applied by the queue in the order they were sent
+enum TimerCommand<E> {
+    Start(u64, Instant, Timer<E>),
+    Cancel(u64),
+    Reschedule(u64, Instant),
+}
+
+enum Timer<E> {
+    Once(E),
+    Every(Recurring<E>),
+}
+
+/// a timer started with `send_every`
+struct Recurring<E> {
+    event: Box<dyn FnMut() -> E + Send>,
+    period: Duration,
+    jitter: Duration,
+}
+
+impl<E> Recurring<E> {
+    fn next_deadline(&self, fired: Instant, now: Instant) -> Instant {
+        next_deadline(fired, now, self.period, self.jitter)
+    }
+}
+
+/// when a recurring timer fires after firing at `fired`, randomly up to `jitter`
+/// early or late. A timer that fell behind skips the missed periods instead of
+/// catching up in a burst.
+fn next_deadline(fired: Instant, now: Instant, period: Duration, jitter: Duration) -> Instant {
+    let next = (fired + period).max(now);
+    if jitter == Duration::from_secs(0) {
+        return next;
+    }
+
+    let mut rng = rand::thread_rng();
+    let jitter = jitter.mul_f64(rng.gen());
+    if rng.gen() {
+        next + jitter
+    } else {
+        next.checked_sub(jitter).map_or(next, |early| early.max(now))
+    }
+}
+
+/// what a bounded queue does with an event sent while it is full
+#[derive(Debug)]
+pub enum Overflow<E> {
+    /// wait for room: `send_async` waits, `send` blocks the thread
+    Block,
+    /// make room by dropping the oldest queued event
+    DropOldest,
+    /// drop the event being sent
+    DropNewest,
+    /// drop the event being sent and deliver the one made by the function
+    /// urgently instead, so the receiver learns it fell behind
+    Signal(fn() -> E),
+}
+
+impl<E> Clone for Overflow<E> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<E> Copy for Overflow<E> {}
+
+/// outcome of `EventSender::send`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sent {
+    Queued,
+    /// a bounded queue was full, the event was handled according to its `Overflow` policy
+    Overflowed,
+    /// the queue is gone
+    Closed,
+}
+
+/// capacity handling of a bounded queue, shared by its senders
+#[derive(Debug)]
+struct Bounded<E> {
+    /// to drop the oldest event from the sending side, one per `Priority`
+    recv: Weak<Vec<Receiver<Queued<E>>>>,
+    overflow: Overflow<E>,
+}
+
+impl<E> Clone for Bounded<E> {
+    fn clone(&self) -> Self {
+        Self {
+            recv: self.recv.clone(),
+            overflow: self.overflow,
+        }
+    }
 }
 
 impl<E> Default for EventQueue<E>
@@ -21,96 +222,305 @@ where
     E: Send + 'static,
 {
     /// create new event queue
-    fn default() -> Self {
-        let (sender, recv) = flume::unbounded();
-        let (immediate_sender, urgent_recv) = flume::unbounded();
+    fn default() -> Self { Self::with_clock(Clock::System) }
+}
+
+impl<E> EventQueue<E>
+where
+    E: Send + 'static,
+{
+    /// create new event queue with timers running on `clock`
+    pub fn with_clock(clock: Clock) -> Self { Self::new(None, clock) }
+
+    /// create new event queue holding at most `capacity` events of each priority.
+    /// Urgent events and timers are not limited.
+    pub fn bounded(capacity: usize, overflow: Overflow<E>, clock: Clock) -> Self {
+        Self::new(Some((capacity, overflow)), clock)
+    }
+
+    fn new(bounds: Option<(usize, Overflow<E>)>, clock: Clock) -> Self {
+        let (tx, recv): (Vec<_>, Vec<_>) = Priority::ALL
+            .iter()
+            .map(|priority| match bounds {
+                Some((capacity, _)) if *priority != Priority::Urgent => flume::bounded(capacity),
+                _ => flume::unbounded(),
+            })
+            .unzip();
         let (timer_sender, timer_recv) = flume::unbounded();
 
-        let sender = EventSender::new(sender, immediate_sender, timer_sender);
+        let overflow_recv = bounds.map(|_| Arc::new(recv.clone()));
+        let bounded = bounds.map(|(_, overflow)| Bounded {
+            recv: overflow_recv.as_ref().map_or_else(Weak::new, Arc::downgrade),
+            overflow,
+        });
+        let sender = EventSender {
+            tx,
+            tx_timer: timer_sender,
+            bounded,
+            next_timer: Arc::new(AtomicU64::new(0)),
+            clock: clock.clone(),
+        };
 
         Self {
-            recv,
             sender,
-            urgent_recv,
+            lanes: recv.into_iter().map(Lane::new).collect(),
+            timer_stream: timer_recv.clone().into_stream(),
             timer_recv,
             timers: BTreeMap::new(),
+            deadlines: HashMap::new(),
+            sleep: None,
+            _overflow_recv: overflow_recv,
+            clock,
         }
     }
-}
 
-impl<E> EventQueue<E>
-where
-    E: Send + 'static,
-{
     pub fn sender(&self) -> &EventSender<E> { &self.sender }
 
+    /// queue depths, timers and the age of the oldest event, e.g. to report
+    pub fn metrics(&mut self) -> QueueMetrics {
+        self.enque_timers();
+        let now = self.clock.now();
+        let mut metrics = QueueMetrics {
+            pending_timers: self.timers.len(),
+            ..QueueMetrics::default()
+        };
+        for (depth, lane) in metrics.depths.iter_mut().zip(&mut self.lanes) {
+            if let Some(queued) = lane.peek() {
+                let age = now.saturating_duration_since(queued.sent_at);
+                metrics.oldest = metrics.oldest.max(Some(age));
+            }
+            *depth = lane.len();
+        }
+        metrics
+    }
+
     fn enque_timers(&mut self) {
-        while let Ok((when, event)) = self.timer_recv.try_recv() {
-            self.timers.insert(when, event);
+        while let Ok(command) = self.timer_recv.try_recv() {
+            self.apply(command);
+        }
+    }
+
+    fn apply(&mut self, command: TimerCommand<E>) {
+        match command {
+            TimerCommand::Start(id, when, timer) => {
+                self.timers.insert((when, id), timer);
+                self.deadlines.insert(id, when);
+            }
+            TimerCommand::Cancel(id) => {
+                if let Some(when) = self.deadlines.remove(&id) {
+                    self.timers.remove(&(when, id));
+                }
+            }
+            TimerCommand::Reschedule(id, new_when) => {
+                if let Some(when) = self.deadlines.get_mut(&id) {
+                    if let Some(timer) = self.timers.remove(&(*when, id)) {
+                        self.timers.insert((new_when, id), timer);
+                        *when = new_when;
+                    }
+                }
+            }
         }
     }
 
     fn next_instant(&self) -> Option<Instant> {
-        self.timers.iter().map(|(instant, _)| *instant).next()
+        self.timers.keys().next().map(|(instant, _)| *instant)
     }
 
     fn next_timed_event(&mut self) -> Option<E> {
-        self.next_instant().and_then(|instant| {
-            if instant < Instant::now() {
-                self.timers.remove(&instant)
-            } else {
-                None
+        let (&(instant, id), _) = self.timers.iter().next()?;
+        let now = self.clock.now();
+        if instant > now {
+            return None;
+        }
+
+        match self.timers.remove(&(instant, id))? {
+            Timer::Once(event) => {
+                self.deadlines.remove(&id);
+                Some(event)
             }
-        })
+            Timer::Every(mut recurring) => {
+                let event = (recurring.event)();
+                let next = recurring.next_deadline(instant, now);
+                self.timers.insert((next, id), Timer::Every(recurring));
+                self.deadlines.insert(id, next);
+                Some(event)
+            }
+        }
     }
 
-    pub async fn recv_async(&mut self) -> Option<E> {
-        self.enque_timers();
+    /// the next event: urgent ones first, then due timers, then the other priorities in order
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<E> {
+        loop {
+            while let Poll::Ready(Some(command)) = self.timer_stream.poll_next_unpin(cx) {
+                self.apply(command);
+            }
+
+            if let Some(event) = self.lanes[Priority::Urgent.index()].poll_next(cx) {
+                return Poll::Ready(event);
+            } else if let Some(event) = self.next_timed_event() {
+                return Poll::Ready(event);
+            }
+            let rest = &mut self.lanes[Priority::High.index()..];
+            if let Some(event) = rest.iter_mut().find_map(|lane| lane.poll_next(cx)) {
+                return Poll::Ready(event);
+            }
 
-        if !self.urgent_recv.is_empty() {
-            self.urgent_recv.recv().ok()
-        } else if let Some(next_timed_event) = self.next_timed_event() {
-            Some(next_timed_event)
-        } else if let Some(next_instant) = self.next_instant() {
-            select! {
-                event = self.urgent_recv.recv_async() => event.ok(),
-                event = self.recv.recv_async() => event.ok(),
-                _ = tokio::time::delay_until(next_instant.into()).fuse() => self.timers.remove(&next_instant),
+            let next_instant = match self.next_instant() {
+                Some(instant) => instant,
+                None => {
+                    self.sleep = None;
+                    return Poll::Pending;
+                }
+            };
+            if self.sleep.as_ref().map(|(deadline, _)| *deadline) != Some(next_instant) {
+                let clock = self.clock.clone();
+                let sleep = async move { clock.sleep_until(next_instant).await };
+                self.sleep = Some((next_instant, Box::pin(sleep)));
             }
-        } else {
-            select! {
-                event = self.urgent_recv.recv_async() => event.ok(),
-                event = self.recv.recv_async() => event.ok(),
+            if let Some((_, sleep)) = &mut self.sleep {
+                futures_util::ready!(sleep.as_mut().poll(cx));
             }
+            // the earliest timer is due, go around again
+            self.sleep = None;
         }
     }
+
+    pub async fn recv_async(&mut self) -> Option<E> {
+        Some(future::poll_fn(|cx| self.poll_recv(cx)).await)
+    }
+}
+
+impl<E> Stream for EventQueue<E>
+where
+    E: Send + 'static,
+{
+    type Item = E;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<E>> {
+        self.get_mut().poll_recv(cx).map(Some)
+    }
 }
 
 #[derive(Debug)]
 pub struct EventSender<E> {
-    tx: Sender<E>,
-    tx_urgent: Sender<E>,
-    tx_timer: Sender<(Instant, E)>,
+    /// one per `Priority`, in order
+    tx: Vec<Sender<Queued<E>>>,
+    tx_timer: Sender<TimerCommand<E>>,
+    bounded: Option<Bounded<E>>,
+    next_timer: Arc<AtomicU64>,
+    clock: Clock,
 }
 
 impl<E> EventSender<E>
 where
     E: Send + 'static,
 {
-    fn new(tx: Sender<E>, tx_urgent: Sender<E>, tx_timer: Sender<(Instant, E)>) -> Self {
-        Self {
-            tx,
-            tx_urgent,
-            tx_timer,
+    /// queue `event` with normal priority, on a full bounded queue as its `Overflow` policy says
+    pub fn send(&self, event: E) -> Sent { self.send_with_priority(event, Priority::Normal) }
+
+    /// like `send`, for events that are more or less important than most
+    pub fn send_with_priority(&self, event: E, priority: Priority) -> Sent {
+        let tx = &self.tx[priority.index()];
+        let mut queued = self.stamp(event);
+        let bounded = match &self.bounded {
+            Some(bounded) if priority != Priority::Urgent => bounded,
+            _ => return tx.send(queued).map_or(Sent::Closed, |_| Sent::Queued),
+        };
+
+        queued = match tx.try_send(queued) {
+            Ok(()) => return Sent::Queued,
+            Err(TrySendError::Disconnected(_)) => return Sent::Closed,
+            Err(TrySendError::Full(queued)) => queued,
+        };
+
+        match bounded.overflow {
+            Overflow::Block => return tx.send(queued).map_or(Sent::Closed, |_| Sent::Overflowed),
+            Overflow::DropOldest => loop {
+                match bounded.recv.upgrade() {
+                    Some(recv) => drop(recv[priority.index()].try_recv()),
+                    None => return Sent::Closed,
+                }
+                queued = match tx.try_send(queued) {
+                    Ok(()) => break,
+                    Err(TrySendError::Disconnected(_)) => return Sent::Closed,
+                    Err(TrySendError::Full(queued)) => queued,
+                };
+            },
+            Overflow::DropNewest => {}
+            Overflow::Signal(overflowed) => self.send_with_urgency(overflowed()),
+        }
+        Sent::Overflowed
+    }
+
+    /// like `send`, but waits for room on a full `Overflow::Block` queue without blocking the thread
+    pub async fn send_async(&self, event: E) -> Sent {
+        match &self.bounded {
+            Some(Bounded {
+                overflow: Overflow::Block,
+                ..
+            }) => {
+                let tx = &self.tx[Priority::Normal.index()];
+                match tx.try_send(self.stamp(event)) {
+                    Ok(()) => Sent::Queued,
+                    Err(TrySendError::Disconnected(_)) => Sent::Closed,
+                    Err(TrySendError::Full(queued)) => {
+                        let sent = tx.send_async(queued).await;
+                        sent.map_or(Sent::Closed, |_| Sent::Overflowed)
+                    }
+                }
+            }
+            _ => self.send(event),
         }
     }
 
-    pub fn send(&self, event: E) { self.tx.send(event).ok(); }
+    pub fn send_with_urgency(&self, event: E) { self.send_with_priority(event, Priority::Urgent); }
+
+    /// events waiting in the queue, of all priorities
+    pub fn queued(&self) -> usize { self.tx.iter().map(Sender::len).sum() }
 
-    pub fn send_with_urgency(&self, event: E) { self.tx_urgent.send(event).ok(); }
+    fn stamp(&self, event: E) -> Queued<E> {
+        Queued {
+            event,
+            sent_at: self.clock.now(),
+        }
+    }
+
+    /// send `event` once `after` has passed, unless the returned timer is cancelled first
+    pub fn send_with_delay(&self, event: E, after: Duration) -> TimerHandle<E> {
+        self.start_timer(after, Timer::Once(event))
+    }
+
+    /// send an event made by `event` right away and then every `period`,
+    /// until the returned timer is cancelled
+    pub fn send_every<F>(&self, event: F, period: Duration) -> TimerHandle<E>
+    where
+        F: FnMut() -> E + Send + 'static,
+    {
+        self.send_every_with_jitter(event, period, Duration::from_secs(0))
+    }
 
-    pub fn send_with_delay(&self, event: E, after: Duration) {
-        self.tx_timer.send((Instant::now() + after, event)).ok();
+    /// like `send_every`, but each period is randomly up to `jitter` longer or
+    /// shorter, so timers started together drift apart instead of firing in lockstep
+    pub fn send_every_with_jitter<F>(&self, event: F, period: Duration, jitter: Duration) -> TimerHandle<E>
+    where
+        F: FnMut() -> E + Send + 'static,
+    {
+        let recurring = Recurring {
+            event: Box::new(event),
+            period,
+            jitter: self.clock.timer_jitter(jitter.min(period)),
+        };
+        self.start_timer(Duration::from_secs(0), Timer::Every(recurring))
+    }
+
+    fn start_timer(&self, after: Duration, timer: Timer<E>) -> TimerHandle<E> {
+        let id = self.next_timer.fetch_add(1, Ordering::Relaxed);
+        let when = self.clock.now() + after;
+        self.tx_timer.send(TimerCommand::Start(id, when, timer)).ok();
+        TimerHandle {
+            id,
+            sender: self.clone(),
+        }
     }
 }
 
@@ -119,10 +529,647 @@ where
     E: Send + 'static,
 {
     fn clone(&self) -> Self {
-        EventSender::new(
-            self.tx.clone(),
-            self.tx_urgent.clone(),
-            self.tx_timer.clone(),
-        )
+        Self {
+            tx: self.tx.clone(),
+            tx_timer: self.tx_timer.clone(),
+            bounded: self.bounded.clone(),
+            next_timer: self.next_timer.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+/// a pending `send_with_delay` event or a `send_every` timer.
+///
+/// Dropping the handle leaves the timer running. Once a one-off event has been
+/// received, cancelling or rescheduling does nothing.
+#[derive(Debug)]
+pub struct TimerHandle<E> {
+    id: u64,
+    sender: EventSender<E>,
+}
+
+impl<E> TimerHandle<E>
+where
+    E: Send + 'static,
+{
+    /// drop the event without sending it, or stop sending recurring events
+    pub fn cancel(self) { self.sender.tx_timer.send(TimerCommand::Cancel(self.id)).ok(); }
+
+    /// send the event `after` from now instead. A recurring timer keeps its period from there.
+    pub fn reschedule(&self, after: Duration) {
+        let when = self.sender.clock.now() + after;
+        self.sender.tx_timer.send(TimerCommand::Reschedule(self.id, when)).ok();
+    }
+}
+
+/// slots per level of a `Wheel`, as bits of a tick
+const WHEEL_BITS: u32 = 6;
+const WHEEL_SLOTS: usize = 1 << WHEEL_BITS;
+/// with 1ms ticks, six levels reach more than two years ahead
+const WHEEL_LEVELS: usize = 6;
+const WHEEL_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// hierarchical timing wheel. Level `n` has 64 slots of 64^n ticks each, timers
+/// move down a level whenever time reaches their slot until they expire on level 0.
+///
+/// Removed and rescheduled timers leave stale references in their old slot,
+/// which are skipped once it comes up.
+struct Wheel<T> {
+    start: Instant,
+    /// every slot before this tick has been processed
+    now: u64,
+    levels: Vec<Level>,
+    entries: HashMap<u64, Entry<T>>,
+    /// tells current slot references from stale ones
+    next_generation: u64,
+}
+
+struct Level {
+    /// bit per slot holding references
+    occupied: u64,
+    /// timer IDs with the generation they were placed with
+    slots: Vec<Vec<(u64, u64)>>,
+}
+
+struct Entry<T> {
+    deadline: Instant,
+    tick: u64,
+    generation: u64,
+    item: T,
+}
+
+impl<T> Wheel<T> {
+    fn new(start: Instant) -> Self {
+        let levels = (0..WHEEL_LEVELS)
+            .map(|_| Level {
+                occupied: 0,
+                slots: (0..WHEEL_SLOTS).map(|_| Vec::new()).collect(),
+            })
+            .collect();
+        Self {
+            start,
+            now: 0,
+            levels,
+            entries: HashMap::new(),
+            next_generation: 0,
+        }
+    }
+
+    /// first tick at or after `instant`, so timers never fire early
+    fn tick_at(&self, instant: Instant) -> u64 {
+        let resolution = WHEEL_RESOLUTION.as_nanos();
+        let since = instant.saturating_duration_since(self.start).as_nanos();
+        since.div_ceil(resolution) as u64
+    }
+
+    fn instant_at(&self, tick: u64) -> Instant {
+        self.start + Duration::from_nanos(tick * WHEEL_RESOLUTION.as_nanos() as u64)
+    }
+
+    fn insert(&mut self, id: u64, deadline: Instant, item: T) {
+        let tick = self.tick_at(deadline);
+        let generation = self.generation();
+        let entry = Entry {
+            deadline,
+            tick,
+            generation,
+            item,
+        };
+        self.entries.insert(id, entry);
+        self.place(id, generation, tick);
+    }
+
+    fn reschedule(&mut self, id: u64, deadline: Instant) {
+        let tick = self.tick_at(deadline);
+        let generation = self.generation();
+        if let Some(entry) = self.entries.get_mut(&id) {
+            entry.deadline = deadline;
+            entry.tick = tick;
+            entry.generation = generation;
+            self.place(id, generation, tick);
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> Option<T> { self.entries.remove(&id).map(|entry| entry.item) }
+
+    fn generation(&mut self) -> u64 {
+        self.next_generation += 1;
+        self.next_generation
+    }
+
+    fn place(&mut self, id: u64, generation: u64, tick: u64) {
+        // beyond the top level, wait in its last slot and get placed again from there
+        let horizon = self.now | ((1 << (WHEEL_BITS * WHEEL_LEVELS as u32)) - 1);
+        let tick = tick.clamp(self.now, horizon);
+        // the highest bit in which the tick differs from now picks the level
+        let differs = (self.now ^ tick) | (WHEEL_SLOTS as u64 - 1);
+        let level = ((63 - differs.leading_zeros()) / WHEEL_BITS) as usize;
+        let slot = (tick >> (WHEEL_BITS * level as u32)) as usize % WHEEL_SLOTS;
+
+        let level = &mut self.levels[level];
+        level.slots[slot].push((id, generation));
+        level.occupied |= 1 << slot;
+    }
+
+    /// level, slot and first tick of the earliest occupied slot. Lower levels
+    /// always come before higher ones.
+    fn next_slot(&self) -> Option<(usize, usize, u64)> {
+        self.levels.iter().enumerate().find_map(|(index, level)| {
+            let shift = WHEEL_BITS * index as u32;
+            let current = (self.now >> shift) as usize % WHEEL_SLOTS;
+            let ahead = level.occupied & (u64::MAX << current);
+            if ahead == 0 {
+                return None;
+            }
+            let slot = ahead.trailing_zeros() as u64;
+            let period_start = self.now & !((1 << (shift + WHEEL_BITS)) - 1);
+            Some((index, slot as usize, period_start + (slot << shift)))
+        })
+    }
+
+    /// when the next slot comes up, possibly only to move timers down a level
+    fn next_deadline(&self) -> Option<Instant> {
+        self.next_slot().map(|(_, _, tick)| self.instant_at(tick))
+    }
+
+    /// process every slot up to `now`, collecting the timers that expired
+    fn advance(&mut self, now: Instant, expired: &mut Vec<(u64, Instant, T)>) {
+        let resolution = WHEEL_RESOLUTION.as_nanos();
+        let target = (now.saturating_duration_since(self.start).as_nanos() / resolution) as u64;
+
+        while let Some((level, slot, tick)) = self.next_slot() {
+            if tick > target {
+                break;
+            }
+            self.now = self.now.max(tick);
+            let level = &mut self.levels[level];
+            level.occupied &= !(1 << slot);
+
+            for (id, generation) in std::mem::take(&mut level.slots[slot]) {
+                let tick = match self.entries.get(&id) {
+                    Some(entry) if entry.generation == generation => entry.tick,
+                    _ => continue,
+                };
+                if tick > self.now {
+                    self.place(id, generation, tick);
+                } else if let Some(entry) = self.entries.remove(&id) {
+                    expired.push((id, entry.deadline, entry.item));
+                }
+            }
+        }
+        self.now = self.now.max(target);
+    }
+}
+
+/// changes to the timers of a `TimerWheel`, applied by its driver in order
+enum WheelCommand {
+    Start(u64, Instant, Fire),
+    Cancel(u64),
+    Reschedule(u64, Instant),
+}
+
+/// what a wheel timer does when it fires
+struct Fire {
+    /// delivers the event, false once nobody is listening anymore
+    deliver: Box<dyn FnMut() -> bool + Send>,
+    period: Option<Duration>,
+    jitter: Duration,
+}
+
+/// timers shared by many receivers, e.g. every client session of a server.
+///
+/// A single `WheelDriver` task keeps them in a hierarchical timing wheel and
+/// sends their events over channels, instead of every receiver running an
+/// `EventQueue` with timers of its own. Timers stop once their receiver is gone.
+#[derive(Clone, Debug)]
+pub struct TimerWheel {
+    tx: Sender<WheelCommand>,
+    next_timer: Arc<AtomicU64>,
+    clock: Clock,
+}
+
+impl TimerWheel {
+    /// create a wheel on `clock`, its timers fire while the driver runs
+    pub fn new(clock: Clock) -> (Self, WheelDriver) {
+        let (tx, commands) = flume::unbounded();
+        let driver = WheelDriver {
+            wheel: Wheel::new(clock.now()),
+            commands,
+            clock: clock.clone(),
+        };
+        let wheel = Self {
+            tx,
+            next_timer: Arc::new(AtomicU64::new(0)),
+            clock,
+        };
+        (wheel, driver)
+    }
+
+    /// send `event` to `to` once `after` has passed, unless the returned timer is cancelled first
+    pub fn send_with_delay<E>(&self, to: &Sender<E>, event: E, after: Duration) -> WheelTimer
+    where
+        E: Send + 'static,
+    {
+        let to = to.clone();
+        let mut event = Some(event);
+        let fire = Fire {
+            deliver: Box::new(move || event.take().is_some_and(|event| to.send(event).is_ok())),
+            period: None,
+            jitter: Duration::from_secs(0),
+        };
+        self.start(after, fire)
+    }
+
+    /// send an event made by `event` to `to` right away and then every `period`,
+    /// until the returned timer is cancelled or the receiver dropped
+    pub fn send_every<E, F>(&self, to: &Sender<E>, event: F, period: Duration) -> WheelTimer
+    where
+        E: Send + 'static,
+        F: FnMut() -> E + Send + 'static,
+    {
+        self.send_every_with_jitter(to, event, period, Duration::from_secs(0))
+    }
+
+    /// like `send_every`, but each period is randomly up to `jitter` longer or
+    /// shorter, so timers started together drift apart instead of firing in lockstep
+    pub fn send_every_with_jitter<E, F>(
+        &self,
+        to: &Sender<E>,
+        mut event: F,
+        period: Duration,
+        jitter: Duration,
+    ) -> WheelTimer
+    where
+        E: Send + 'static,
+        F: FnMut() -> E + Send + 'static,
+    {
+        let to = to.clone();
+        let fire = Fire {
+            deliver: Box::new(move || to.send(event()).is_ok()),
+            period: Some(period),
+            jitter: self.clock.timer_jitter(jitter.min(period)),
+        };
+        self.start(Duration::from_secs(0), fire)
+    }
+
+    fn start(&self, after: Duration, fire: Fire) -> WheelTimer {
+        let id = self.next_timer.fetch_add(1, Ordering::Relaxed);
+        let when = self.clock.now() + after;
+        self.tx.send(WheelCommand::Start(id, when, fire)).ok();
+        WheelTimer {
+            id,
+            wheel: self.clone(),
+        }
+    }
+}
+
+/// a timer of a `TimerWheel`, like `TimerHandle` for an `EventQueue`
+#[derive(Debug)]
+pub struct WheelTimer {
+    id: u64,
+    wheel: TimerWheel,
+}
+
+impl WheelTimer {
+    /// drop the event without sending it, or stop sending recurring events
+    pub fn cancel(self) { self.wheel.tx.send(WheelCommand::Cancel(self.id)).ok(); }
+
+    /// send the event `after` from now instead. A recurring timer keeps its period from there.
+    pub fn reschedule(&self, after: Duration) {
+        let when = self.wheel.clock.now() + after;
+        self.wheel.tx.send(WheelCommand::Reschedule(self.id, when)).ok();
+    }
+}
+
+/// runs the timers of a `TimerWheel`, until every handle to it is dropped
+pub struct WheelDriver {
+    wheel: Wheel<Fire>,
+    commands: Receiver<WheelCommand>,
+    clock: Clock,
+}
+
+impl WheelDriver {
+    pub async fn run(mut self) {
+        let mut expired = Vec::new();
+        loop {
+            while let Ok(command) = self.commands.try_recv() {
+                self.apply(command);
+            }
+
+            let now = self.clock.now();
+            self.wheel.advance(now, &mut expired);
+            for (id, deadline, mut fire) in expired.drain(..) {
+                if !(fire.deliver)() {
+                    continue;
+                }
+                if let Some(period) = fire.period {
+                    let next = next_deadline(deadline, now, period, fire.jitter);
+                    self.wheel.insert(id, next, fire);
+                }
+            }
+
+            let next_deadline = self.wheel.next_deadline();
+            let clock = &self.clock;
+            let timer = async move {
+                match next_deadline {
+                    Some(deadline) => clock.sleep_until(deadline).await,
+                    None => future::pending().await,
+                }
+            };
+
+            // a timer change may move the next deadline, go around again. Fused so a
+            // disconnected channel is still polled, it would count as terminated otherwise.
+            let command = select! {
+                command = self.commands.recv_async().fuse() => match command {
+                    Ok(command) => Some(command),
+                    Err(_) => return,
+                },
+                _ = timer.fuse() => None,
+            };
+            if let Some(command) = command {
+                self.apply(command);
+            }
+        }
+    }
+
+    fn apply(&mut self, command: WheelCommand) {
+        match command {
+            WheelCommand::Start(id, when, fire) => self.wheel.insert(id, when, fire),
+            WheelCommand::Cancel(id) => {
+                self.wheel.remove(id);
+            }
+            WheelCommand::Reschedule(id, when) => self.wheel.reschedule(id, when),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    /// every event received until the queue has nothing left due
+    async fn drain<E: Send + 'static>(queue: &mut EventQueue<E>) -> Vec<E> {
+        let mut events = Vec::new();
+        while let Some(Some(event)) = queue.recv_async().now_or_never() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn timers_may_share_a_deadline() {
+        let clock = SimulatedClock::default();
+        let mut queue = EventQueue::with_clock(Clock::Simulated(clock.clone()));
+        for event in 0..3 {
+            queue.sender().send_with_delay(event, Duration::from_secs(1));
+        }
+
+        assert!(drain(&mut queue).await.is_empty());
+        clock.advance(Duration::from_secs(1)).await;
+        assert_eq!(drain(&mut queue).await, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_timers_never_fire() {
+        let clock = SimulatedClock::default();
+        let mut queue = EventQueue::with_clock(Clock::Simulated(clock.clone()));
+        let cancelled = queue.sender().send_with_delay("cancelled", Duration::from_secs(1));
+        queue.sender().send_with_delay("kept", Duration::from_secs(2));
+        cancelled.cancel();
+
+        clock.advance(Duration::from_secs(5)).await;
+        assert_eq!(drain(&mut queue).await, vec!["kept"]);
+    }
+
+    #[tokio::test]
+    async fn rescheduled_timers_move() {
+        let clock = SimulatedClock::default();
+        let mut queue = EventQueue::with_clock(Clock::Simulated(clock.clone()));
+        let later = queue.sender().send_with_delay("later", Duration::from_secs(1));
+        let sooner = queue.sender().send_with_delay("sooner", Duration::from_secs(10));
+        later.reschedule(Duration::from_secs(3));
+        sooner.reschedule(Duration::from_secs(2));
+
+        clock.advance(Duration::from_secs(2)).await;
+        assert_eq!(drain(&mut queue).await, vec!["sooner"]);
+        clock.advance(Duration::from_secs(1)).await;
+        assert_eq!(drain(&mut queue).await, vec!["later"]);
+    }
+
+    #[tokio::test]
+    async fn recurring_timers_fire_every_period_until_cancelled() {
+        let clock = SimulatedClock::default();
+        let mut queue = EventQueue::with_clock(Clock::Simulated(clock.clone()));
+        let mut count = 0;
+        let timer = queue.sender().send_every(
+            move || {
+                count += 1;
+                count
+            },
+            Duration::from_secs(2),
+        );
+
+        assert_eq!(drain(&mut queue).await, vec![1]);
+        clock.advance(Duration::from_secs(5)).await;
+        assert_eq!(drain(&mut queue).await, vec![2, 3]);
+
+        timer.cancel();
+        clock.advance(Duration::from_secs(10)).await;
+        assert!(drain(&mut queue).await.is_empty());
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let recurring = Recurring {
+            event: Box::new(|| ()),
+            period: Duration::from_secs(2),
+            jitter: Duration::from_millis(200),
+        };
+        let fired = Instant::now();
+        for _ in 0..100 {
+            let next = recurring.next_deadline(fired, fired);
+            assert!(next >= fired + Duration::from_millis(1800));
+            assert!(next <= fired + Duration::from_millis(2200));
+        }
+    }
+
+    #[tokio::test]
+    async fn bounded_queues_apply_their_overflow_policy() {
+        let fill = |overflow| {
+            let mut queue = EventQueue::bounded(2, overflow, Clock::System);
+            let sent: Vec<Sent> = (1..=3).map(|event| queue.sender().send(event)).collect();
+            assert_eq!(sent, vec![Sent::Queued, Sent::Queued, Sent::Overflowed]);
+            async move { drain(&mut queue).await }
+        };
+
+        assert_eq!(fill(Overflow::DropOldest).await, vec![2, 3]);
+        assert_eq!(fill(Overflow::DropNewest).await, vec![1, 2]);
+        assert_eq!(fill(Overflow::Signal(|| 0)).await, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn bounded_queues_close_with_the_queue() {
+        for overflow in [Overflow::Block, Overflow::DropOldest, Overflow::DropNewest] {
+            let queue = EventQueue::bounded(1, overflow, Clock::System);
+            let sender = queue.sender().clone();
+            assert_eq!(sender.send(1), Sent::Queued);
+            drop(queue);
+
+            assert_eq!(sender.send(2), Sent::Closed);
+            assert_eq!(sender.send_async(3).await, Sent::Closed);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocking_queues_wait_for_room() {
+        let mut queue = EventQueue::bounded(1, Overflow::Block, Clock::System);
+        let sender = queue.sender().clone();
+        assert_eq!(sender.send_async(1).await, Sent::Queued);
+        let blocked = tokio::spawn(async move { sender.send_async(2).await });
+        let _ = tokio::task::yield_now().await;
+
+        assert_eq!(queue.recv_async().await, Some(1));
+        assert_eq!(blocked.await.unwrap(), Sent::Overflowed);
+        assert_eq!(queue.recv_async().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn higher_priorities_are_received_first() {
+        let mut queue = EventQueue::default();
+        let sender = queue.sender();
+        sender.send_with_priority("low", Priority::Low);
+        sender.send("normal");
+        sender.send_with_priority("high", Priority::High);
+        sender.send_with_urgency("urgent");
+        sender.send_with_delay("timer", Duration::from_secs(0));
+
+        assert_eq!(drain(&mut queue).await, vec!["urgent", "timer", "high", "normal", "low"]);
+    }
+
+    #[tokio::test]
+    async fn queues_work_with_stream_combinators() {
+        let clock = SimulatedClock::default();
+        let queue = EventQueue::with_clock(Clock::Simulated(clock.clone()));
+        queue.sender().send_every(|| 1, Duration::from_secs(1));
+        queue.sender().send(10);
+
+        let sums = queue.scan(0, |sum, event| {
+            *sum += event;
+            future::ready(Some(*sum))
+        });
+        let sums = tokio::spawn(sums.take(3).collect::<Vec<_>>());
+        clock.advance(Duration::from_secs(1)).await;
+        assert_eq!(sums.await.unwrap(), vec![1, 11, 12]);
+    }
+
+    #[tokio::test]
+    async fn metrics_report_depths_timers_and_age() {
+        let clock = SimulatedClock::default();
+        let mut queue = EventQueue::with_clock(Clock::Simulated(clock.clone()));
+        queue.sender().send("first");
+        clock.advance(Duration::from_secs(3)).await;
+        queue.sender().send_with_priority("second", Priority::Low);
+        queue.sender().send_with_delay("timer", Duration::from_secs(10));
+
+        let metrics = queue.metrics();
+        assert_eq!(metrics.depth(Priority::Normal), 1);
+        assert_eq!(metrics.depth(Priority::Low), 1);
+        assert_eq!(metrics.queued(), 2);
+        assert_eq!(metrics.pending_timers, 1);
+        assert_eq!(metrics.oldest, Some(Duration::from_secs(3)));
+
+        assert_eq!(drain(&mut queue).await, vec!["first", "second"]);
+        assert_eq!(queue.metrics().oldest, None);
+    }
+
+    #[test]
+    fn wheel_expires_timers_in_order_across_levels() {
+        let start = Instant::now();
+        let mut wheel = Wheel::new(start);
+        let delays = [36_000_000, 5, 0, 70, 300_000, 4_100, 70, 5_000];
+        for (id, millis) in delays.iter().enumerate() {
+            wheel.insert(id as u64, start + Duration::from_millis(*millis), *millis);
+        }
+
+        let mut fired = Vec::new();
+        let mut expired = Vec::new();
+        while let Some(deadline) = wheel.next_deadline() {
+            wheel.advance(deadline, &mut expired);
+            for (_, deadline, millis) in expired.drain(..) {
+                assert_eq!(deadline, start + Duration::from_millis(millis));
+                assert_eq!(wheel.now, millis, "expired at the wrong tick");
+                fired.push(millis);
+            }
+        }
+        assert_eq!(fired, vec![0, 5, 70, 70, 4_100, 5_000, 300_000, 36_000_000]);
+    }
+
+    #[test]
+    fn wheel_skips_moved_and_removed_timers() {
+        let start = Instant::now();
+        let mut wheel = Wheel::new(start);
+        wheel.insert(0, start + Duration::from_secs(5), "removed");
+        wheel.insert(1, start + Duration::from_secs(5), "moved");
+        wheel.insert(2, start + Duration::from_secs(6), "kept");
+        wheel.remove(0);
+        wheel.reschedule(1, start + Duration::from_secs(7));
+
+        let mut expired = Vec::new();
+        wheel.advance(start + Duration::from_secs(6), &mut expired);
+        assert_eq!(expired.drain(..).map(|(_, _, item)| item).collect::<Vec<_>>(), vec!["kept"]);
+        wheel.advance(start + Duration::from_secs(10), &mut expired);
+        assert_eq!(expired.drain(..).map(|(_, _, item)| item).collect::<Vec<_>>(), vec!["moved"]);
+        assert!(wheel.entries.is_empty());
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn wheel_timers_stop_with_their_receiver() {
+        let clock = SimulatedClock::default();
+        let (wheel, driver) = TimerWheel::new(Clock::Simulated(clock.clone()));
+        tokio::spawn(driver.run());
+        let (tx, rx) = flume::unbounded();
+        let check = wheel.send_with_delay(&tx, "check", Duration::from_secs(5));
+        wheel.send_every(&tx, || "tick", Duration::from_secs(2));
+
+        clock.advance(Duration::from_secs(4)).await;
+        check.reschedule(Duration::from_secs(5));
+        clock.advance(Duration::from_secs(4)).await;
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec!["tick", "tick", "tick", "tick", "tick"]);
+        clock.advance(Duration::from_secs(1)).await;
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec!["check"]);
+
+        drop(rx);
+        clock.advance(Duration::from_secs(2)).await;
+        assert_eq!(clock.next_deadline(), None, "driver still waits for a timer");
+    }
+
+    #[tokio::test]
+    async fn simulated_wheels_fire_without_jitter() {
+        let clock = SimulatedClock::default();
+        let (wheel, driver) = TimerWheel::new(Clock::Simulated(clock.clone()));
+        tokio::spawn(driver.run());
+        let (tx, rx) = flume::unbounded();
+        let period = Duration::from_secs(1);
+        wheel.send_every_with_jitter(&tx, || (), period, Duration::from_millis(200));
+
+        // one right away, then exactly every second so runs repeat
+        clock.advance(Duration::from_millis(20500)).await;
+        assert_eq!(rx.try_iter().count(), 21);
+    }
+
+    #[tokio::test]
+    async fn wheel_driver_stops_with_its_last_handle() {
+        let clock = SimulatedClock::default();
+        let (wheel, driver) = TimerWheel::new(Clock::Simulated(clock.clone()));
+        let driver = tokio::spawn(driver.run());
+        let (tx, _rx) = flume::unbounded();
+        wheel.send_every(&tx, || (), Duration::from_secs(1));
+
+        drop(wheel);
+        clock.advance(Duration::from_secs(1)).await;
+        assert!(driver.now_or_never().is_some());
     }
 }
@@ -0,0 +1,104 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use crate::clock::Clock;
+
+/// weight of a new sample in the smoothed round trip time, as in TCP (RFC 6298)
+const RTT_ALPHA: f64 = 0.125;
+/// pings awaiting a pong, older ones are forgotten
+const MAX_OUTSTANDING: usize = 16;
+
+/// sends pings and keeps a smoothed round trip time estimate from the pongs.
+///
+/// A ping carries a nonce and the time it was sent, in microseconds since
+/// this pinger was created. The peer echoes both back, but the round trip is
+/// timed from when we sent the nonce, so a peer can't fake its latency by
+/// changing the timestamp.
+#[derive(Debug)]
+pub struct Pinger {
+    clock: Clock,
+    epoch: Instant,
+    next_nonce: u64,
+    /// when each unanswered ping was sent, by nonce
+    outstanding: BTreeMap<u64, Instant>,
+    srtt: Option<Duration>,
+}
+
+impl Default for Pinger {
+    fn default() -> Self { Self::new(Clock::System) }
+}
+
+impl Pinger {
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            epoch: clock.now(),
+            clock,
+            next_nonce: 0,
+            outstanding: BTreeMap::new(),
+            srtt: None,
+        }
+    }
+
+    fn now_micros(&self) -> u64 { (self.clock.now() - self.epoch).as_micros() as u64 }
+
+    /// nonce and timestamp for the next ping
+    pub fn ping(&mut self) -> (u64, u64) {
+        let nonce = self.next_nonce;
+        self.next_nonce += 1;
+        self.outstanding.insert(nonce, self.clock.now());
+        if self.outstanding.len() > MAX_OUTSTANDING {
+            self.outstanding.pop_first();
+        }
+        (nonce, self.now_micros())
+    }
+
+    /// record the pong for an earlier ping, returning the updated estimate.
+    ///
+    /// Pongs that don't match an unanswered ping we sent are ignored, the
+    /// echoed timestamp is not trusted.
+    pub fn on_pong(&mut self, nonce: u64) -> Option<Duration> {
+        let sent = self.outstanding.remove(&nonce)?;
+        let sample = self.clock.now().saturating_duration_since(sent);
+        let srtt = match self.srtt {
+            Some(srtt) => srtt.mul_f64(1.0 - RTT_ALPHA) + sample.mul_f64(RTT_ALPHA),
+            None => sample,
+        };
+        self.srtt = Some(srtt);
+        Some(srtt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    #[tokio::test]
+    async fn round_trips_are_timed_by_the_sender() {
+        let clock = SimulatedClock::default();
+        let mut pinger = Pinger::new(Clock::Simulated(clock.clone()));
+        let (nonce, _) = pinger.ping();
+
+        clock.advance(Duration::from_millis(80)).await;
+        assert_eq!(pinger.on_pong(nonce), Some(Duration::from_millis(80)));
+    }
+
+    #[test]
+    fn unknown_and_repeated_pongs_are_ignored() {
+        let mut pinger = Pinger::default();
+        let (nonce, _) = pinger.ping();
+
+        assert_eq!(pinger.on_pong(nonce + 1), None);
+        assert!(pinger.on_pong(nonce).is_some());
+        assert_eq!(pinger.on_pong(nonce), None);
+
+        // pings that were never answered are eventually forgotten
+        let (oldest, _) = pinger.ping();
+        for _ in 0..MAX_OUTSTANDING {
+            pinger.ping();
+        }
+        assert_eq!(pinger.on_pong(oldest), None);
+    }
+}
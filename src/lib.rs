@@ -0,0 +1,26 @@
+//! A socket-based client-server system to play a virtual ball.
+//!
+//! The protocol lives in [`message`] and [`encoding`], the server in
+//! [`server::GameServer`] and the client in [`connection::Client`] (headless)
+//! and [`client::ClientApp`] (interactive).
+
+pub mod bans;
+pub mod bot;
+pub mod client;
+pub mod clock;
+pub mod command;
+pub mod config;
+pub mod connection;
+pub mod encoding;
+pub mod events;
+pub mod game;
+pub mod loadtest;
+pub mod logging;
+pub mod message;
+pub mod server;
+pub mod utils;
+
+mod latency;
+mod limits;
+mod session;
+mod tui;
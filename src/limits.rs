@@ -0,0 +1,171 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    clock::Clock,
+    config::{RateLimit, RateLimitConfig},
+    message::MessageKind,
+};
+
+/// token bucket rate limiter.
+///
+/// Holds up to `burst` tokens and refills `rate` tokens per second, every
+/// allowed action takes one token.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+    clock: Clock,
+}
+
+impl TokenBucket {
+    /// create a full bucket
+    pub fn new(rate: f64, burst: u32, clock: Clock) -> Self {
+        Self {
+            rate,
+            burst: f64::from(burst),
+            tokens: f64::from(burst),
+            last_refill: clock.now(),
+            clock,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+    }
+
+    /// take a token if one is available
+    pub fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// whether the bucket has refilled completely, i.e. it holds no state worth keeping
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.burst
+    }
+}
+
+/// outcome of checking a client message against its session limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// drop the message and warn the client
+    Throttle { warnings_left: u32 },
+    /// the client kept flooding after being warned
+    Kick,
+}
+
+/// per message type rate limits of a single client session
+#[derive(Debug)]
+pub struct MessageLimiter {
+    pass_ball: Option<TokenBucket>,
+    heartbeat: Option<TokenBucket>,
+    ping: Option<TokenBucket>,
+    violations: u32,
+    max_violations: u32,
+    /// when the client was last throttled
+    last_violation: Option<Instant>,
+    forgive_after: Duration,
+    clock: Clock,
+}
+
+impl MessageLimiter {
+    pub fn new(config: &RateLimitConfig, clock: &Clock) -> Self {
+        let bucket = |limit: &RateLimit| TokenBucket::new(limit.rate, limit.burst, clock.clone());
+        Self {
+            pass_ball: config.pass_ball.as_ref().map(bucket),
+            heartbeat: config.heartbeat.as_ref().map(bucket),
+            ping: config.ping.as_ref().map(bucket),
+            violations: 0,
+            max_violations: config.max_violations,
+            last_violation: None,
+            forgive_after: config.forgive_after,
+            clock: clock.clone(),
+        }
+    }
+
+    pub fn check(&mut self, kind: MessageKind) -> Verdict {
+        let bucket = match kind {
+            MessageKind::PassBall => self.pass_ball.as_mut(),
+            MessageKind::Heartbeat => self.heartbeat.as_mut(),
+            MessageKind::Ping | MessageKind::Pong => self.ping.as_mut(),
+            MessageKind::Leave => None,
+        };
+
+        if bucket.is_none_or(TokenBucket::try_take) {
+            return Verdict::Allow;
+        }
+
+        // a client that behaved for a while starts over, only sustained flooding gets it kicked
+        let now = self.clock.now();
+        if self.last_violation.is_some_and(|last| now >= last + self.forgive_after) {
+            self.violations = 0;
+        }
+        self.last_violation = Some(now);
+
+        self.violations += 1;
+        if self.violations >= self.max_violations {
+            Verdict::Kick
+        } else {
+            Verdict::Throttle {
+                warnings_left: self.max_violations - self.violations,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    fn limiter(clock: &SimulatedClock) -> MessageLimiter {
+        let config = RateLimitConfig {
+            pass_ball: Some(RateLimit { rate: 1.0, burst: 1 }),
+            max_violations: 3,
+            forgive_after: Duration::from_secs(10),
+            ..RateLimitConfig::default()
+        };
+        MessageLimiter::new(&config, &Clock::Simulated(clock.clone()))
+    }
+
+    #[test]
+    fn flooding_clients_are_warned_then_kicked() {
+        let clock = SimulatedClock::default();
+        let mut limiter = limiter(&clock);
+
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Allow);
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Throttle { warnings_left: 2 });
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Throttle { warnings_left: 1 });
+        assert_eq!(limiter.check(MessageKind::Leave), Verdict::Allow);
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Kick);
+    }
+
+    #[tokio::test]
+    async fn violations_are_forgiven_after_a_quiet_period() {
+        let clock = SimulatedClock::default();
+        let mut limiter = limiter(&clock);
+
+        limiter.check(MessageKind::PassBall);
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Throttle { warnings_left: 2 });
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Throttle { warnings_left: 1 });
+
+        // sending within the limit for a while counts as quiet
+        clock.advance(Duration::from_secs(5)).await;
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Allow);
+        clock.advance(Duration::from_secs(5)).await;
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Allow);
+        assert_eq!(limiter.check(MessageKind::PassBall), Verdict::Throttle { warnings_left: 2 });
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use futures_util::{future, SinkExt, StreamExt};
+use rand::seq::SliceRandom;
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::{
+    game::Player,
+    message::{ToClient, ToServer, WhoPassed},
+    utils,
+};
+
+/// load test parameters
+#[derive(Clone, Debug)]
+pub struct LoadTestOptions {
+    pub server_addr: String,
+    /// number of simulated clients
+    pub clients: usize,
+    /// how long to run the workload once everyone connected
+    pub duration: Duration,
+    /// spread the connects evenly over this time
+    pub ramp_up: Duration,
+    /// how long a client holds the ball before passing it
+    pub think_time: Duration,
+    /// give up on a connect after this long
+    pub connect_timeout: Duration,
+}
+
+impl Default for LoadTestOptions {
+    fn default() -> Self {
+        Self {
+            server_addr: String::new(),
+            clients: 100,
+            duration: Duration::from_secs(10),
+            ramp_up: Duration::from_secs(0),
+            think_time: Duration::from_millis(100),
+            connect_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// heartbeat interval used until the server announces its own
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// what a single simulated client observed
+#[derive(Default)]
+struct ClientReport {
+    connect_time: Option<Duration>,
+    connect_error: Option<String>,
+    /// time from sending a pass until its broadcast came back
+    latencies: Vec<Duration>,
+    sent: u64,
+    received: u64,
+    /// why the connection ended before the workload finished
+    disconnect: Option<String>,
+}
+
+/// run the load test and print a summary
+pub async fn run(opts: LoadTestOptions) {
+    let LoadTestOptions {
+        duration,
+        ramp_up,
+        think_time,
+        connect_timeout,
+        ..
+    } = opts;
+
+    println!(
+        "Load testing {} with {} clients for {}..",
+        opts.server_addr,
+        opts.clients,
+        humantime::format_duration(duration)
+    );
+
+    let start = Instant::now();
+    let end = start + ramp_up + duration;
+    let clients = (0..opts.clients).map(|i| {
+        let connect_at = start + ramp_up.mul_f64(i as f64 / opts.clients.max(1) as f64);
+        let addr = opts.server_addr.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_until(connect_at.into()).await;
+            simulate_client(addr, connect_timeout, think_time, end).await
+        })
+    });
+
+    let reports: Vec<ClientReport> = future::join_all(clients)
+        .await
+        .into_iter()
+        .map(|report| report.unwrap_or_default())
+        .collect();
+
+    print_summary(&reports, start.elapsed());
+}
+
+async fn simulate_client(
+    addr: String,
+    connect_timeout: Duration,
+    think_time: Duration,
+    end: Instant,
+) -> ClientReport {
+    let mut report = ClientReport::default();
+
+    let started = Instant::now();
+    let stream = match tokio::time::timeout(connect_timeout, TcpStream::connect(&addr)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            report.connect_error = Some(err.to_string());
+            return report;
+        }
+        Err(_) => {
+            report.connect_error = Some("timed out".into());
+            return report;
+        }
+    };
+    report.connect_time = Some(started.elapsed());
+    let (mut from_server, mut to_server) = utils::frame_socket::<ToClient, ToServer>(stream);
+
+    let mut myself = None;
+    let mut players: Vec<Player> = Vec::new();
+    let mut holder = None;
+    // when to pass the ball on, set while holding it
+    let mut pass_at: Option<Instant> = None;
+    // the pass in flight and when it was sent
+    let mut pending: Option<(Player, Instant)> = None;
+
+    let mut heartbeat = tokio::time::interval(DEFAULT_HEARTBEAT_INTERVAL);
+    let finish = tokio::time::delay_until(end.into());
+    futures_util::pin_mut!(finish);
+
+    loop {
+        let next_pass = async {
+            match pass_at {
+                Some(at) => tokio::time::delay_until(at.into()).await,
+                None => future::pending().await,
+            }
+        };
+
+        let outgoing = tokio::select! {
+            _ = &mut finish => break,
+
+            _ = heartbeat.tick() => ToServer::Heartbeat,
+
+            _ = next_pass => {
+                pass_at = None;
+                let others: Vec<Player> =
+                    players.iter().copied().filter(|p| Some(*p) != myself).collect();
+                match others.choose(&mut rand::thread_rng()) {
+                    Some(to) => {
+                        pending = Some((*to, Instant::now()));
+                        ToServer::PassBall(*to)
+                    }
+                    // alone in the game, try again once someone joins
+                    None => continue,
+                }
+            }
+
+            msg = from_server.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(err)) => {
+                        report.disconnect = Some(format!("invalid message: {}", err));
+                        return report;
+                    }
+                    None => {
+                        report.disconnect = Some("connection closed by server".into());
+                        return report;
+                    }
+                };
+                report.received += 1;
+
+                // answer latency probes like a real client
+                if let ToClient::Ping(nonce, sent_at) = msg {
+                    ToServer::Pong(nonce, sent_at)
+                } else {
+                    match msg {
+                        ToClient::InitialState(player, state) => {
+                            myself = Some(player);
+                            holder = state.info.player_with_ball;
+                            players = state.players;
+                        }
+                        ToClient::PlayerJoin(player) => {
+                            players.push(player);
+                            let holding = myself.is_some() && holder == myself;
+                            if holding && pass_at.is_none() && pending.is_none() {
+                                pass_at = Some(Instant::now());
+                            }
+                        }
+                        ToClient::PlayerLeave(player) => players.retain(|p| *p != player),
+                        ToClient::PassBall(to, who) => {
+                            if let Some((target, sent_at)) = pending {
+                                if target == to && matches!(who, WhoPassed::Player) {
+                                    report.latencies.push(sent_at.elapsed());
+                                    pending = None;
+                                }
+                            }
+                            holder = Some(to);
+                            if Some(to) == myself {
+                                pass_at = Some(Instant::now() + think_time);
+                            }
+                        }
+                        ToClient::ActionRejected { .. } => {
+                            pending = None;
+                            if myself.is_some() && holder == myself {
+                                pass_at = Some(Instant::now() + think_time);
+                            }
+                        }
+                        ToClient::Disconnect(reason) | ToClient::Kicked(reason) => {
+                            report.disconnect = Some(reason);
+                            return report;
+                        }
+                        ToClient::Hello {
+                            heartbeat_interval, ..
+                        } => heartbeat = tokio::time::interval(heartbeat_interval),
+                        ToClient::Queued { .. }
+                        | ToClient::Throttled { .. }
+                        | ToClient::ShuttingDown { .. }
+                        | ToClient::Heartbeat
+                        | ToClient::Ping(..)
+                        | ToClient::Pong(..)
+                        | ToClient::PlayerLatencies(_) => {}
+                    }
+                    continue;
+                }
+            }
+        };
+
+        if let Err(err) = to_server.send(outgoing).await {
+            report.disconnect = Some(format!("send failed: {}", err));
+            return report;
+        }
+        report.sent += 1;
+    }
+
+    let _ = to_server.send(ToServer::Leave).await;
+    debug!(player = ?myself, "simulated client done");
+    report
+}
+
+fn print_summary(reports: &[ClientReport], elapsed: Duration) {
+    let total = reports.len();
+    let connected = reports.iter().filter(|r| r.connect_time.is_some()).count();
+    let percent = |n: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            n as f64 * 100.0 / total as f64
+        }
+    };
+
+    println!();
+    println!(
+        "connections: {}/{} succeeded ({:.1}%)",
+        connected,
+        total,
+        percent(connected)
+    );
+
+    let mut connect_errors: BTreeMap<&str, usize> = BTreeMap::new();
+    for err in reports.iter().filter_map(|r| r.connect_error.as_deref()) {
+        *connect_errors.entry(err).or_default() += 1;
+    }
+    for (err, count) in connect_errors {
+        println!("  {:>6} x {}", count, err);
+    }
+
+    let mut connect_times: Vec<Duration> = reports.iter().filter_map(|r| r.connect_time).collect();
+    print_percentiles("connect time", &mut connect_times);
+
+    let mut latencies: Vec<Duration> = reports
+        .iter()
+        .flat_map(|r| r.latencies.iter().copied())
+        .collect();
+    println!(
+        "passes: {} acknowledged ({:.1}/s)",
+        latencies.len(),
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    print_percentiles("pass to broadcast latency", &mut latencies);
+
+    let sent: u64 = reports.iter().map(|r| r.sent).sum();
+    let received: u64 = reports.iter().map(|r| r.received).sum();
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "messages: {} sent ({:.1}/s), {} received ({:.1}/s)",
+        sent,
+        sent as f64 / secs,
+        received,
+        received as f64 / secs
+    );
+
+    let mut reasons: BTreeMap<&str, usize> = BTreeMap::new();
+    for reason in reports.iter().filter_map(|r| r.disconnect.as_deref()) {
+        *reasons.entry(reason).or_default() += 1;
+    }
+    let dropped: usize = reasons.values().sum();
+    println!(
+        "disconnects: {} ({:.1}% of all clients)",
+        dropped,
+        percent(dropped)
+    );
+    for (reason, count) in reasons {
+        println!("  {:>6} x {}", count, reason);
+    }
+}
+
+fn print_percentiles(name: &str, samples: &mut [Duration]) {
+    if samples.is_empty() {
+        println!("{}: no samples", name);
+        return;
+    }
+
+    samples.sort_unstable();
+    let at = |p: f64| {
+        let index = ((samples.len() - 1) as f64 * p).round() as usize;
+        format!("{:.2}ms", samples[index].as_secs_f64() * 1000.0)
+    };
+    println!(
+        "{}: p50 {}, p90 {}, p99 {}, max {}",
+        name,
+        at(0.5),
+        at(0.9),
+        at(0.99),
+        at(1.0)
+    );
+}
@@ -0,0 +1,50 @@
+use tracing_subscriber::{filter::ParseError, fmt, EnvFilter};
+
+/// environment variable holding the log filter, e.g. `BALL_LOG=debug`
+pub const LOG_ENV: &str = "BALL_LOG";
+
+/// environment variable selecting the log format (`json` or `text`)
+pub const LOG_FORMAT_ENV: &str = "BALL_LOG_FORMAT";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid log filter")]
+    Filter(#[from] ParseError),
+
+    #[error("could not install log subscriber")]
+    Init(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// install the global tracing subscriber.
+///
+/// Diagnostics are always written to stderr so they never interleave with
+/// the user-facing client output on stdout. The filter is taken from `level`
+/// if given, then `BALL_LOG`, then `default_level`.
+pub fn init(
+    level: Option<&str>,
+    json: bool,
+    default_level: &str,
+) -> Result<(), Error> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => {
+            EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new(default_level))
+        }
+    };
+
+    let json = json
+        || std::env::var(LOG_FORMAT_ENV)
+            .map(|format| format.eq_ignore_ascii_case("json"))
+            .unwrap_or(false);
+
+    let builder = fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    if json {
+        builder.json().with_current_span(true).try_init()
+    } else {
+        builder.try_init()
+    }
+    .map_err(Error::Init)
+}
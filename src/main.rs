@@ -1,20 +1,27 @@
-use crate::server::GameServer;
 use argh::FromArgs;
+use ball_pass_game::{
+    bot::Strategy,
+    client::{self, ClientOptions},
+    config::{BindAddr, ServerConfig},
+    connection::ConnectOptions,
+    loadtest::{self, LoadTestOptions},
+    logging,
+    server::{self, GameServer},
+};
 use futures_util::future::{AbortHandle, Abortable};
-use std::error::Error;
-
-mod client;
-mod encoding;
-mod events;
-mod game;
-mod message;
-mod server;
-mod session;
-mod utils;
+use std::{error::Error, path::PathBuf, time::Duration};
 
 /// A socket-based client-server system to play a virtual ball.
 #[derive(FromArgs)]
 struct Opt {
+    /// log filter, e.g. `info` or `ball_pass_game=debug` (overrides BALL_LOG)
+    #[argh(option)]
+    log_level: Option<String>,
+
+    /// emit logs as JSON lines
+    #[argh(switch)]
+    log_json: bool,
+
     #[argh(subcommand)]
     cmd: Option<SubOpt>,
 }
@@ -22,8 +29,181 @@ struct Opt {
 #[derive(FromArgs)]
 #[argh(subcommand)]
 enum SubOpt {
-    Client(client::CliOpts),
-    Server(server::CliOpts),
+    Client(ClientOpts),
+    Server(ServerOpts),
+    Loadtest(LoadtestOpts),
+}
+
+/// run client
+#[derive(FromArgs, Default)]
+#[argh(subcommand, name = "client")]
+struct ClientOpts {
+    /// address of server to connect to.
+    #[argh(option, short = 'h')]
+    server_addr: String,
+
+    /// use the full-screen terminal interface
+    #[argh(switch)]
+    tui: bool,
+
+    /// reconnect and rejoin the game when the connection is lost
+    #[argh(switch)]
+    reconnect: bool,
+
+    /// give up reconnecting after this many failed attempts, retries forever if unset
+    #[argh(option)]
+    max_retries: Option<u32>,
+
+    /// consider the connection dead after this long without hearing from the server,
+    /// e.g. `10s` (default: the timeout announced by the server)
+    #[argh(option, from_str_fn(parse_duration))]
+    server_timeout: Option<Duration>,
+
+    /// seed for the reconnect delays, to replay a run exactly (default: random)
+    #[argh(option)]
+    seed: Option<u64>,
+
+    /// play automatically instead of reading commands: random, next, echo or hold:<duration>
+    #[argh(option)]
+    bot: Option<Strategy>,
+
+    /// how long a bot waits before passing the ball on, e.g. `500ms` (default)
+    #[argh(option, from_str_fn(parse_duration))]
+    think_time: Option<Duration>,
+}
+
+/// run server
+#[derive(FromArgs)]
+#[argh(subcommand, name = "server")]
+struct ServerOpts {
+    /// path to a TOML config file, flags below override its values
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+
+    /// address to listen on, e.g. `0.0.0.0`, `::` or `[::1]:4000`; repeat for more listeners
+    #[argh(option, short = 'b')]
+    bind: Vec<BindAddr>,
+
+    /// port for server to run on (default 4565)
+    #[argh(option, short = 'p')]
+    port: Option<u16>,
+
+    /// maximum number of players in the game, others wait in a queue
+    #[argh(option)]
+    max_players: Option<usize>,
+
+    /// maximum number of clients waiting for a free spot
+    #[argh(option)]
+    max_queued: Option<usize>,
+
+    /// ban list file, reloaded on SIGHUP
+    #[argh(option)]
+    bans: Option<PathBuf>,
+
+    /// disconnect clients silent for this long, e.g. `5s`
+    #[argh(option, from_str_fn(parse_duration))]
+    heartbeat_timeout: Option<Duration>,
+}
+
+/// run many simulated clients against a server and report how it holds up
+#[derive(FromArgs)]
+#[argh(subcommand, name = "loadtest")]
+struct LoadtestOpts {
+    /// address of server to connect to.
+    #[argh(option, short = 'h')]
+    server_addr: String,
+
+    /// number of simulated clients (default 100)
+    #[argh(option, short = 'n')]
+    clients: Option<usize>,
+
+    /// how long to run the workload, e.g. `30s` (default 10s)
+    #[argh(option, from_str_fn(parse_duration))]
+    duration: Option<Duration>,
+
+    /// spread the connects evenly over this time, e.g. `2s` (default: all at once)
+    #[argh(option, from_str_fn(parse_duration))]
+    ramp_up: Option<Duration>,
+
+    /// how long a client holds the ball before passing it, e.g. `200ms` (default 100ms)
+    #[argh(option, from_str_fn(parse_duration))]
+    think_time: Option<Duration>,
+
+    /// give up on a connect after this long (default 5s)
+    #[argh(option, from_str_fn(parse_duration))]
+    connect_timeout: Option<Duration>,
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    humantime::parse_duration(value).map_err(|err| err.to_string())
+}
+
+impl ServerOpts {
+    /// build the server from the config file if given, with command line overrides
+    fn build_server(self) -> Result<GameServer, server::BuildError> {
+        let mut builder = GameServer::builder();
+
+        if let Some(path) = &self.config {
+            builder = builder.config(ServerConfig::from_file(path)?);
+        }
+
+        if !self.bind.is_empty() {
+            builder = builder.bind(self.bind);
+        }
+
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+
+        if let Some(max_players) = self.max_players {
+            builder = builder.max_players(max_players);
+        }
+
+        if let Some(max_queued) = self.max_queued {
+            builder = builder.max_queued(max_queued);
+        }
+
+        if let Some(bans) = self.bans {
+            builder = builder.ban_file(bans);
+        }
+
+        if let Some(timeout) = self.heartbeat_timeout {
+            builder = builder.heartbeat_timeout(timeout);
+        }
+
+        builder.build()
+    }
+}
+
+impl From<ClientOpts> for ClientOptions {
+    fn from(opts: ClientOpts) -> Self {
+        Self {
+            connect: ConnectOptions {
+                server_addr: opts.server_addr,
+                reconnect: opts.reconnect,
+                max_retries: opts.max_retries,
+                server_timeout: opts.server_timeout,
+                seed: opts.seed,
+            },
+            tui: opts.tui,
+            bot: opts.bot,
+            think_time: opts.think_time,
+        }
+    }
+}
+
+impl From<LoadtestOpts> for LoadTestOptions {
+    fn from(opts: LoadtestOpts) -> Self {
+        let defaults = LoadTestOptions::default();
+        Self {
+            server_addr: opts.server_addr,
+            clients: opts.clients.unwrap_or(defaults.clients),
+            duration: opts.duration.unwrap_or(defaults.duration),
+            ramp_up: opts.ramp_up.unwrap_or(defaults.ramp_up),
+            think_time: opts.think_time.unwrap_or(defaults.think_time),
+            connect_timeout: opts.connect_timeout.unwrap_or(defaults.connect_timeout),
+        }
+    }
 }
 
 #[tokio::main]
@@ -33,12 +213,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // set default command to 'client'
     let cmd = cli
         .cmd
-        .unwrap_or_else(|| SubOpt::Client(client::CliOpts::default()));
+        .unwrap_or_else(|| SubOpt::Client(ClientOpts::default()));
+
+    // the client only reports problems by default, its output is for the player.
+    // Anything written to the terminal would garble the full-screen interface.
+    let default_level = match &cmd {
+        SubOpt::Client(opt) if opt.tui => "off",
+        SubOpt::Client(_) => "warn",
+        SubOpt::Server(_) => "info",
+        SubOpt::Loadtest(_) => "warn",
+    };
+    logging::init(cli.log_level.as_deref(), cli.log_json, default_level)?;
 
     match cmd {
         SubOpt::Client(opt) => {
-            let addr = opt.server_addr;
-            let mut app = client::start(addr).await;
+            let mut app = client::start(opt.into()).await?;
 
             // listen for ctrl_c
             let tx = app.sender();
@@ -57,25 +246,50 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
 
         SubOpt::Server(opts) => {
-            let addr = format!("127.0.0.1:{}", opts.port);
-            let server = GameServer::default();
+            let server = opts.build_server().unwrap_or_else(|err| exit_with(&err));
+
+            // reload the ban list on SIGHUP
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{signal, SignalKind};
+
+                let tx = server.sender().clone();
+                let mut hangup = signal(SignalKind::hangup())?;
+                tokio::spawn(async move {
+                    while hangup.recv().await.is_some() {
+                        tx.send(server::Event::ReloadBans);
+                    }
+                });
+            }
 
             // listen for ctrl_c
+            // the first ctrl-c starts a graceful shutdown, the second forces it
             let tx = server.sender().clone();
             let ctrlc = async move {
-                let _ = tokio::signal::ctrl_c().await;
+                loop {
+                    let _ = tokio::signal::ctrl_c().await;
 
-                println!("✨ Ctrl-C received. Stopping..");
-                tx.send_with_urgency(server::Event::CtrlC)
+                    tracing::info!("ctrl-c received, stopping");
+                    tx.send_with_urgency(server::Event::CtrlC)
+                }
             };
 
             let (ctrlc_abort_handle, abort_registration) = AbortHandle::new_pair();
             tokio::spawn(Abortable::new(ctrlc, abort_registration));
 
-            server.run(&addr).await?;
+            server.run().await?;
             ctrlc_abort_handle.abort();
         }
+
+        SubOpt::Loadtest(opts) => loadtest::run(opts.into()).await,
     };
 
     Ok(())
 }
+
+/// report a startup error and exit
+fn exit_with(err: &dyn Error) -> ! {
+    let cause = err.source().map(|cause| cause.to_string());
+    tracing::error!(cause = cause.as_deref(), "{}", err);
+    std::process::exit(2);
+}
@@ -1,25 +1,108 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 use crate::game::{GameInfo, Player};
 
-pub const HEARTBEAT_INTERVAL_SECS: u64 = 5;
-
 /// Client -> Server
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ToServer {
     Heartbeat,
     PassBall(Player),
     Leave,
+    /// measure the round trip time: a nonce and the send time, echoed back in a `Pong`
+    Ping(u64, u64),
+    /// answer to a `ToClient::Ping`, with its nonce and send time unchanged
+    Pong(u64, u64),
+}
+
+impl ToServer {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            ToServer::Heartbeat => MessageKind::Heartbeat,
+            ToServer::PassBall(_) => MessageKind::PassBall,
+            ToServer::Leave => MessageKind::Leave,
+            ToServer::Ping(..) => MessageKind::Ping,
+            ToServer::Pong(..) => MessageKind::Pong,
+        }
+    }
+}
+
+/// type of a client message, without its payload
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MessageKind {
+    Heartbeat,
+    PassBall,
+    Leave,
+    Ping,
+    Pong,
 }
 
 /// Server -> Client
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ToClient {
+    /// first message on every connection: how often each side sends heartbeats,
+    /// and after how much silence the other side is considered gone
+    Hello {
+        heartbeat_interval: Duration,
+        heartbeat_timeout: Duration,
+    },
+    /// sent every `heartbeat_interval` so the client can tell the server is alive
+    Heartbeat,
     InitialState(Player, GameState),
+    /// the game is full, the client waits at `position` (1-based) in the queue
+    Queued {
+        position: usize,
+    },
     PlayerJoin(Player),
     PlayerLeave(Player),
     PassBall(Player, WhoPassed),
+    /// the server refused to carry out an action requested by the client
+    ActionRejected {
+        action: Action,
+        reason: RejectReason,
+    },
+    /// the client sent too many messages of `kind`, they are being dropped.
+    /// The client is kicked after `warnings_left` more violations.
+    Throttled {
+        kind: MessageKind,
+        warnings_left: u32,
+    },
+    /// the server is shutting down and disconnects everyone in `in_secs` seconds
+    ShuttingDown {
+        in_secs: u64,
+    },
+    /// measure the round trip time: a nonce and the send time, echoed back in a `Pong`
+    Ping(u64, u64),
+    /// answer to a `ToServer::Ping`, with its nonce and send time unchanged
+    Pong(u64, u64),
+    /// smoothed round trip time of every player the server has measured
+    PlayerLatencies(Vec<(Player, Duration)>),
     Disconnect(String),
+    /// disconnected for good, e.g. banned or kicked for flooding. Reconnecting won't help.
+    Kicked(String),
+}
+
+/// a client request that changes the game
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Action {
+    PassBall(Player),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// only the player holding the ball can pass it
+    NotHolder,
+    /// the target player is not in the game
+    UnknownTarget,
+    /// the game rules don't allow passing the ball to yourself
+    SelfPass,
+    /// the client sent too many requests
+    RateLimited,
+    /// the client is still waiting in the queue
+    NotInGame,
+    /// the game is not accepting actions, e.g. the server is shutting down
+    GameNotRunning,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -27,6 +110,7 @@ pub enum WhoPassed {
     Player,
     PlayerWithBallLeft,
     PlayerStumbledUponBall,
+    HoldLimitReached,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
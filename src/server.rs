@@ -1,89 +1,422 @@
-use argh::FromArgs;
-use futures_util::StreamExt;
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
-use tokio::net::{TcpListener, TcpStream};
+use futures_util::{
+    future::{self, AbortHandle, Abortable, Aborted},
+    stream, SinkExt, Stream, StreamExt,
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn, Instrument};
 
 use crate::{
-    events::{EventQueue, EventSender},
+    bans::{self, BanList},
+    clock::Clock,
+    config::{self, BindAddr, GameMode, ServerConfig},
+    events::{EventQueue, EventSender, Priority, Sent, TimerHandle, TimerWheel, WheelDriver},
     game::{GameInfo, Player},
-    message::{GameState, ToClient, WhoPassed},
-    session::ClientSession,
+    limits::TokenBucket,
+    message::{Action, GameState, RejectReason, ToClient, ToServer, WhoPassed},
+    encoding::Frame,
+    session::{ClientSession, Outgoing},
     utils,
 };
 
-/// run server
-#[derive(FromArgs)]
-#[argh(subcommand, name = "server")]
-pub struct CliOpts {
-    /// port for server to run on
-    #[argh(option, short = 'p', default = "4565")]
-    pub port: u16,
-}
-
 pub enum Event {
     CtrlC,
     PlayerLeft(Player),
     PassBall(Player, Player),
+    /// hot potato: the player has held the ball for too long
+    HoldExpired(Player),
+    /// re-read the ban list file
+    ReloadBans,
+    /// shutdown countdown, seconds left until players are disconnected
+    ShutdownTick(u64),
+    /// a session measured a new smoothed round trip time to its client
+    Latency(Player, Duration),
+    /// send everyone the latencies of all players
+    BroadcastLatencies,
+    /// log event queue metrics, see `ServerConfig::metrics_interval`
+    ReportMetrics,
+}
+
+/// server side handle of a running client session
+struct SessionHandle {
+    sender: EventSender<Outgoing>,
+    peer_addr: SocketAddr,
+    task: JoinHandle<Result<(), Aborted>>,
+    /// stops the session without waiting for it, e.g. while it is stuck writing to the socket
+    abort: AbortHandle,
+    /// stopped for falling behind, waiting for the server to process its leave
+    lagging: bool,
+    latency: Option<Duration>,
+}
+
+/// per address connection bookkeeping
+struct PeerState {
+    connections: usize,
+    joins: Option<TokenBucket>,
 }
 
-#[derive(Default)]
 pub struct GameServer {
-    connected_players: HashMap<Player, EventSender<ToClient>>,
+    config: ServerConfig,
+    /// ordered by player, so iterating and broadcasting is the same on every run
+    connected_players: BTreeMap<Player, SessionHandle>,
+    /// clients waiting for a spot in the game, in order of arrival
+    waiting_players: VecDeque<(Player, SessionHandle)>,
+    peers: HashMap<IpAddr, PeerState>,
+    bans: BanList,
     event_queue: EventQueue<Event>,
     id_counter: usize,
+    /// hot potato: passes the ball on unless it is passed in time
+    hold_timer: Option<TimerHandle<Event>>,
     game: GameInfo,
+    shutting_down: bool,
+    listeners: Vec<TcpListener>,
+    clock: Clock,
+    /// session timers, driven by a task started in `run`
+    timers: TimerWheel,
+    timer_driver: Option<WheelDriver>,
+}
+
+/// players per latency report, keeping each report well below the frame size limit
+const LATENCIES_PER_MESSAGE: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError {
+    #[error(transparent)]
+    Config(#[from] config::Error),
+
+    #[error(transparent)]
+    Bans(#[from] bans::Error),
+}
+
+/// configures a `GameServer`, starting from the default config
+#[derive(Default)]
+pub struct GameServerBuilder {
+    config: ServerConfig,
+    bans: Option<BanList>,
+    clock: Clock,
+}
+
+impl GameServerBuilder {
+    /// replace the whole config, e.g. one read with `ServerConfig::from_file`
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// addresses to listen on, replacing the configured ones
+    pub fn bind(mut self, addrs: impl IntoIterator<Item = BindAddr>) -> Self {
+        self.config.bind = addrs.into_iter().collect();
+        self
+    }
+
+    /// port for bind addresses without one, `0` picks a free port
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    pub fn max_players(mut self, max_players: usize) -> Self {
+        self.config.max_players = Some(max_players);
+        self
+    }
+
+    pub fn max_queued(mut self, max_queued: usize) -> Self {
+        self.config.max_queued = Some(max_queued);
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.config.heartbeat.interval = interval;
+        self
+    }
+
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.config.heartbeat.timeout = timeout;
+        self
+    }
+
+    pub fn game_mode(mut self, mode: GameMode) -> Self {
+        self.config.game.mode = mode;
+        self
+    }
+
+    /// ban list file, loaded on build and reloaded with `Event::ReloadBans`
+    pub fn ban_file(mut self, path: PathBuf) -> Self {
+        self.config.bans = Some(path);
+        self
+    }
+
+    /// use an already loaded ban list instead of the configured file
+    pub fn bans(mut self, bans: BanList) -> Self {
+        self.bans = Some(bans);
+        self
+    }
+
+    /// time source for timers and timeouts, e.g. a simulated clock in tests
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// validate the config and load the ban list
+    pub fn build(self) -> Result<GameServer, BuildError> {
+        self.config.validate()?;
+        let bans = match (self.bans, &self.config.bans) {
+            (Some(bans), _) => bans,
+            (None, Some(path)) => BanList::load(path)?,
+            (None, None) => BanList::default(),
+        };
+        Ok(GameServer::new(self.config, bans, self.clock))
+    }
 }
 
 impl GameServer {
+    pub fn builder() -> GameServerBuilder { GameServerBuilder::default() }
+
+    fn new(config: ServerConfig, bans: BanList, clock: Clock) -> Self {
+        let (timers, timer_driver) = TimerWheel::new(clock.clone());
+        Self {
+            config,
+            bans,
+            connected_players: BTreeMap::new(),
+            waiting_players: VecDeque::new(),
+            peers: HashMap::new(),
+            event_queue: EventQueue::with_clock(clock.clone()),
+            id_counter: 0,
+            hold_timer: None,
+            game: GameInfo::default(),
+            shutting_down: false,
+            listeners: Vec::new(),
+            clock,
+            timers,
+            timer_driver: Some(timer_driver),
+        }
+    }
+
     pub fn sender(&self) -> &EventSender<Event> { self.event_queue.sender() }
 
     fn players(&self) -> Vec<Player> { self.connected_players.keys().cloned().collect() }
 
+    /// every session, players in the game first and then the waiting queue
+    fn sessions(&self) -> impl Iterator<Item = (&Player, &SessionHandle)> {
+        let waiting = self.waiting_players.iter().map(|(player, session)| (player, session));
+        self.connected_players.iter().chain(waiting)
+    }
+
+    /// the session of a player in the game or waiting to join
+    fn session(&self, player: Player) -> Option<&SessionHandle> {
+        self.sessions().find_map(|(p, session)| (*p == player).then_some(session))
+    }
+
+    fn session_mut(&mut self, player: Player) -> Option<&mut SessionHandle> {
+        if self.connected_players.contains_key(&player) {
+            return self.connected_players.get_mut(&player);
+        }
+        let position = self.queue_position(player)?;
+        self.waiting_players.get_mut(position).map(|(_, session)| session)
+    }
+
     fn gen_unique_id(&mut self) -> Player {
         self.id_counter += 1;
         Player(self.id_counter)
     }
 
+    /// the player with the next higher ID, wrapping around to the lowest
+    fn player_after(&self, player: Player) -> Option<Player> {
+        let players = self.connected_players.keys();
+        let after = players.clone().filter(|p| p.0 > player.0).min_by_key(|p| p.0);
+        after.or_else(|| players.min_by_key(|p| p.0)).cloned()
+    }
+
     fn on_client_disconnect(&mut self, player_leaving: Player) {
-        if let Some(_player_session) = self.connected_players.remove(&player_leaving) {
-            println!("{} left the game!", player_leaving);
+        if let Some(session) = self.connected_players.remove(&player_leaving) {
+            self.release_peer(session.peer_addr.ip());
+            info!(player = player_leaving.0, "player left the game");
+            self.broadcast(ToClient::PlayerLeave(player_leaving));
 
-            let player_with_ball = self.game.player_with_ball.unwrap();
-            if player_leaving == player_with_ball {
-                if let Some(next_player) = self.connected_players.keys().next().cloned() {
-                    print!(".. passing ball to another player ...");
+            if self.game.player_with_ball == Some(player_leaving) {
+                if let Some(next_player) = self.player_after(player_leaving) {
+                    debug!(to = next_player.0, "holder left, passing ball to another player");
                     self.pass_ball(next_player);
                 } else {
                     self.game.player_with_ball = None;
                 }
             }
 
-            println!("players left in game: {:?}", self.players());
+            debug!(players = ?self.players(), "players left in game");
+            self.promote_waiting();
+        } else if let Some(position) = self.queue_position(player_leaving) {
+            info!(player = player_leaving.0, "player left the waiting queue");
+            if let Some((_, session)) = self.waiting_players.remove(position) {
+                self.release_peer(session.peer_addr.ip());
+            }
+            self.update_queue_positions();
+        }
+    }
+
+    fn on_reload_bans(&mut self) {
+        if let Err(err) = self.bans.reload() {
+            let cause = std::error::Error::source(&err).map(|cause| cause.to_string());
+            error!(cause = cause.as_deref(), "{}, keeping previous bans", err);
+            return;
+        }
+        info!(bans = self.bans.len(), "ban list reloaded");
+
+        // disconnect anyone covered by a new ban, their sessions report back when closed
+        for (player, session) in self.sessions() {
+            if let Some(ban) = self.bans.check(session.peer_addr.ip()) {
+                info!(player = player.0, peer = %session.peer_addr, "disconnecting banned player");
+                session.sender.send_with_urgency(ToClient::Kicked(ban.message()).into());
+            }
+        }
+    }
+
+    /// check the per address limits for a new connection and count it if admitted
+    fn admit_peer(&mut self, ip: IpAddr) -> Result<(), &'static str> {
+        let limits = &self.config.connections;
+        let clock = &self.clock;
+
+        // forget addresses that hold no connections and have fully recovered their join budget
+        self.peers.retain(|_, peer| {
+            peer.connections > 0 || peer.joins.as_mut().is_some_and(|bucket| !bucket.is_full())
+        });
+
+        let peer = self.peers.entry(ip).or_insert_with(|| PeerState {
+            connections: 0,
+            joins: limits
+                .join_rate
+                .map(|rate| TokenBucket::new(rate, limits.join_burst, clock.clone())),
+        });
+
+        if peer.connections >= limits.max_per_ip.unwrap_or(usize::MAX) {
+            return Err("Too many connections from your address");
+        }
+
+        if let Some(joins) = &mut peer.joins {
+            if !joins.try_take() {
+                return Err("Joining too quickly, try again later");
+            }
+        }
+
+        peer.connections += 1;
+        Ok(())
+    }
+
+    fn release_peer(&mut self, ip: IpAddr) {
+        if let Some(peer) = self.peers.get_mut(&ip) {
+            peer.connections = peer.connections.saturating_sub(1);
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        let max_players = self.config.max_players.unwrap_or(usize::MAX);
+        self.connected_players.len() >= max_players
+    }
+
+    fn queue_position(&self, player: Player) -> Option<usize> {
+        self.waiting_players.iter().position(|(p, _)| *p == player)
+    }
+
+    /// move waiting clients into the game while there is room, first come first served
+    fn promote_waiting(&mut self) {
+        let mut promoted = false;
+        while !self.is_full() {
+            match self.waiting_players.pop_front() {
+                Some((player, session)) => {
+                    info!(player = player.0, "promoting player from the waiting queue");
+                    self.join(player, session);
+                    promoted = true;
+                }
+                None => break,
+            }
+        }
+
+        if promoted {
+            self.update_queue_positions();
+        }
+    }
+
+    fn update_queue_positions(&self) {
+        for (position, (_, session)) in self.waiting_players.iter().enumerate() {
+            let position = position + 1;
+            session.sender.send(ToClient::Queued { position }.into());
         }
     }
 
     /// handle stream of TcpStream
     fn on_client_connect(&mut self, peer_addr: SocketAddr, st: TcpStream) {
+        if let Some(ban) = self.bans.check(peer_addr.ip()) {
+            info!(peer = %peer_addr, reason = %ban.reason, "rejecting banned address");
+            reject(st, ToClient::Kicked(ban.message()));
+            return;
+        }
+
+        let max_queued = self.config.max_queued.unwrap_or(usize::MAX);
+        if self.is_full() && self.waiting_players.len() >= max_queued {
+            info!(peer = %peer_addr, "game and waiting queue are full, rejecting connection");
+            reject(st, ToClient::Disconnect("Game is full".into()));
+            return;
+        }
+
+        if let Err(reason) = self.admit_peer(peer_addr.ip()) {
+            info!(peer = %peer_addr, reason, "rejecting connection");
+            reject(st, ToClient::Disconnect(reason.into()));
+            return;
+        }
+
         let player = self.gen_unique_id();
         let sender = self.sender().clone();
         let socket = utils::frame_socket(st);
 
-        let players = self.players();
-        let game = self.game.clone();
-        let initial_state = GameState {
-            players,
-            info: game,
+        let (clock, timers) = (self.clock.clone(), self.timers.clone());
+        let mut session =
+            ClientSession::new(player, peer_addr, sender, socket, &self.config, clock, timers);
+        let sender = session.sender().clone();
+        let span = session.span();
+        let (abort, registration) = AbortHandle::new_pair();
+        let session = Abortable::new(async move { session.start().await }, registration);
+        let task = tokio::spawn(session.instrument(span));
+        let handle = SessionHandle {
+            sender,
+            peer_addr,
+            task,
+            abort,
+            lagging: false,
+            latency: None,
         };
 
-        let mut session = ClientSession::new(player, peer_addr, sender, socket);
-        let session_sender = session.sender().clone();
-        tokio::spawn(async move { session.start(initial_state).await });
+        if self.is_full() {
+            let position = self.waiting_players.len() + 1;
+            info!(player = player.0, peer = %peer_addr, position, "game is full, player queued");
+            handle.sender.send(ToClient::Queued { position }.into());
+            self.waiting_players.push_back((player, handle));
+        } else {
+            info!(player = player.0, peer = %peer_addr, "player joined the game");
+            self.join(player, handle);
+        }
+    }
+
+    /// add a player to the game and update everyone on them
+    fn join(&mut self, player: Player, session: SessionHandle) {
+        let initial_state = GameState {
+            players: self.players(),
+            info: self.game.clone(),
+        };
+        session.sender.send(ToClient::InitialState(player, initial_state).into());
 
-        self.connected_players.insert(player, session_sender);
+        self.connected_players.insert(player, session);
 
         // update all users on this user
-        println!("{} joined the game!", player);
-        log::info!("\t players in game: {:?}", self.players());
+        debug!(players = ?self.players(), "players in game");
         self.broadcast(ToClient::PlayerJoin(player));
 
         if self.game.player_with_ball.is_none() {
@@ -95,90 +428,382 @@ impl GameServer {
     fn pass_ball(&mut self, receiving: Player) {
         let who = if let Some(who_passed) = &self.game.player_with_ball {
             if self.connected_players.contains_key(who_passed) {
-                println!(
-                    "{} passed the bass -> {}",
+                info!(
+                    from = who_passed.0,
+                    to = receiving.0,
+                    "{} passed the ball -> {}",
                     who_passed,
                     who_passed.as_ref_str(&receiving)
                 );
                 WhoPassed::Player
             } else {
-                println!("(system) ball passed -> {}", receiving);
+                info!(to = receiving.0, "(system) ball passed -> {}", receiving);
                 WhoPassed::PlayerWithBallLeft
             }
         } else {
-            println!("(system) first player: ball passed -> {}", receiving);
+            info!(to = receiving.0, "(system) first player: ball passed -> {}", receiving);
             WhoPassed::PlayerStumbledUponBall
         };
 
+        self.pass_ball_as(receiving, who);
+    }
+
+    fn pass_ball_as(&mut self, receiving: Player, who: WhoPassed) {
         if self.connected_players.contains_key(&receiving) {
             self.game.player_with_ball = Some(receiving);
             self.broadcast(ToClient::PassBall(receiving, who));
+
+            if self.config.game.mode == GameMode::HotPotato {
+                if let Some(timer) = self.hold_timer.take() {
+                    timer.cancel();
+                }
+                let expired = Event::HoldExpired(receiving);
+                let timer = self
+                    .sender()
+                    .send_with_delay(expired, self.config.game.hold_limit);
+                self.hold_timer = Some(timer);
+            }
         } else {
-            println!("failed to pass the ball to {}", receiving);
+            warn!(to = receiving.0, "failed to pass the ball to {}", receiving);
         }
     }
 
     fn on_ball_pass(&mut self, sender: Player, receiving: Player) {
-        if let Some(player_with_ball) = &self.game.player_with_ball {
-            if player_with_ball != &sender {
-                println!(
-                    "{} tried to pass the ball to {} but doesn't have the ball",
+        match self.check_pass(sender, receiving) {
+            Ok(()) => self.pass_ball(receiving),
+            Err(reason) => {
+                debug!(
+                    from = sender.0,
+                    to = receiving.0,
+                    ?reason,
+                    "{} tried to pass the ball to {}",
                     sender,
                     sender.as_ref_str(&receiving)
                 );
-                return;
+                self.send_to(
+                    sender,
+                    ToClient::ActionRejected {
+                        action: Action::PassBall(receiving),
+                        reason,
+                    },
+                );
             }
         }
+    }
+
+    /// whether `sender` may pass the ball to `receiving` right now
+    fn check_pass(&self, sender: Player, receiving: Player) -> Result<(), RejectReason> {
+        if self.shutting_down {
+            return Err(RejectReason::GameNotRunning);
+        }
+
+        if !self.connected_players.contains_key(&sender) {
+            return Err(RejectReason::NotInGame);
+        }
+
+        if self.game.player_with_ball != Some(sender) {
+            return Err(RejectReason::NotHolder);
+        }
 
-        self.pass_ball(receiving);
+        if !self.connected_players.contains_key(&receiving) {
+            return Err(RejectReason::UnknownTarget);
+        }
+
+        if sender == receiving && !self.config.game.rules.allow_self_pass {
+            return Err(RejectReason::SelfPass);
+        }
+
+        Ok(())
+    }
+
+    /// send a message to a single player, in the game or waiting to join
+    fn send_to(&self, player: Player, msg: ToClient) {
+        if let Some(session) = self.session(player) {
+            session.sender.send(msg.into());
+        }
+    }
+
+    fn on_hold_expired(&mut self, holder: Player) {
+        self.hold_timer = None;
+        if self.game.player_with_ball != Some(holder) {
+            return;
+        }
+
+        if let Some(next_player) = self.player_after(holder) {
+            info!(from = holder.0, to = next_player.0, "hold limit reached, passing ball");
+            self.pass_ball_as(next_player, WhoPassed::HoldLimitReached);
+        }
+    }
+
+    /// send a message to every player, disconnecting those that can't keep up
+    fn broadcast(&mut self, msg: ToClient) { self.broadcast_with_priority(msg, Priority::Normal) }
+
+    fn broadcast_with_priority(&mut self, msg: ToClient, priority: Priority) {
+        if let Some(frame) = encode_broadcast(&msg) {
+            let lagging = send_each(self.connected_players.iter(), &frame, priority);
+            lagging.into_iter().for_each(|player| self.drop_lagging(player));
+        }
+    }
+
+    /// broadcast to players in the game and in the waiting queue
+    fn broadcast_all(&mut self, msg: ToClient) {
+        if let Some(frame) = encode_broadcast(&msg) {
+            let lagging = send_each(self.sessions(), &frame, Priority::Normal);
+            lagging.into_iter().for_each(|player| self.drop_lagging(player));
+        }
+    }
+
+    /// disconnect a client whose messages are piling up. Its session is likely
+    /// stuck writing to a socket the client doesn't read, so it is stopped
+    /// instead of asked to leave. The leave itself is handled as a separate
+    /// event, as this runs in the middle of broadcasts.
+    fn drop_lagging(&mut self, player: Player) {
+        if let Some(session) = self.session_mut(player).filter(|session| !session.lagging) {
+            warn!(player = player.0, peer = %session.peer_addr, "client can't keep up, disconnecting");
+            session.lagging = true;
+            session.abort.abort();
+            self.event_queue.sender().send_with_urgency(Event::PlayerLeft(player));
+        }
     }
 
-    fn broadcast(&self, msg: ToClient) {
-        self.connected_players.iter().for_each(|(_, player)| {
-            player.send(msg.clone());
+    fn on_latency(&mut self, player: Player, rtt: Duration) {
+        if let Some(session) = self.session_mut(player) {
+            session.latency = Some(rtt);
+        }
+    }
+
+    fn on_broadcast_latencies(&mut self) {
+        let latencies: Vec<(Player, Duration)> = self
+            .connected_players
+            .iter()
+            .filter_map(|(player, session)| Some((*player, session.latency?)))
+            .collect();
+        // only informative, anything about the game goes first. Split up so large
+        // games don't outgrow a frame, clients merge the parts.
+        for chunk in latencies.chunks(LATENCIES_PER_MESSAGE) {
+            let msg = ToClient::PlayerLatencies(chunk.to_vec());
+            self.broadcast_with_priority(msg, Priority::Low);
+        }
+    }
+
+    fn on_report_metrics(&mut self) {
+        let metrics = self.event_queue.metrics();
+        let send_queue_max = self.sessions().map(|(_, session)| session.sender.queued()).max();
+        info!(
+            urgent = metrics.depth(Priority::Urgent),
+            high = metrics.depth(Priority::High),
+            normal = metrics.depth(Priority::Normal),
+            low = metrics.depth(Priority::Low),
+            timers = metrics.pending_timers,
+            oldest = ?metrics.oldest,
+            client_send_queue_max = send_queue_max.unwrap_or(0),
+            "event queue metrics"
+        );
+    }
+
+    /// announce the shutdown and count down to disconnecting everyone
+    fn on_shutdown_tick(&mut self, in_secs: u64) {
+        self.shutting_down = true;
+        info!(in_secs, "shutting down");
+        self.broadcast_all(ToClient::ShuttingDown { in_secs });
+        self.sender()
+            .send_with_delay(Event::ShutdownTick(in_secs - 1), Duration::from_secs(1));
+    }
+
+    /// disconnect every session and wait, bounded by the flush timeout, for them to close.
+    /// Another ctrl-c stops waiting.
+    async fn disconnect_all(&mut self) {
+        let waiting = self.waiting_players.drain(..);
+        let players = std::mem::take(&mut self.connected_players);
+        let sessions: Vec<_> = players.into_iter().chain(waiting).collect();
+        info!(sessions = sessions.len(), "disconnecting players");
+
+        let tasks = sessions.into_iter().map(|(_, session)| {
+            session
+                .sender
+                .send_with_urgency(ToClient::Disconnect("Server Shutdown".into()).into());
+            session.task
         });
+        let flush_timeout = self.config.shutdown.flush_timeout;
+        let closed = self.clock.timeout(flush_timeout, future::join_all(tasks));
+
+        let event_queue = &mut self.event_queue;
+        let forced = async move {
+            loop {
+                if let Some(Event::CtrlC) = event_queue.recv_async().await {
+                    break;
+                }
+            }
+        };
+
+        tokio::select! {
+            result = closed => {
+                if result.is_none() {
+                    warn!("not all sessions closed in time");
+                }
+            }
+            _ = forced => warn!("ctrl-c received again, forcing shutdown"),
+        }
     }
 
-    /// start server listener on given address
-    pub async fn run(mut self, addr: &str) -> Result<(), std::io::Error> {
-        let mut tcp_listener = TcpListener::bind(addr).await?.map(|stream| {
-            let st = stream.unwrap();
-            let addr = st.peer_addr().unwrap();
+    /// bind the listeners and return their local addresses, e.g. to find out
+    /// which port was picked for port `0`. `run` calls this if needed.
+    pub fn listen(&mut self) -> io::Result<Vec<SocketAddr>> {
+        let mut addrs = Vec::new();
+        for addr in self.config.listen_addrs() {
+            let listener = utils::bind_listener(addr)?;
+            let local_addr = listener.local_addr()?;
+            info!(addr = %local_addr, "listening");
+            self.listeners.push(listener);
+            addrs.push(local_addr);
+        }
+        Ok(addrs)
+    }
+
+    /// accept connections on all configured addresses and run the game until shut down
+    pub async fn run(mut self) -> Result<(), std::io::Error> {
+        if self.listeners.is_empty() {
+            self.listen()?;
+        }
+        let listeners = std::mem::take(&mut self.listeners);
+
+        // accept from every listener into the same game
+        let keepalive = self.config.tcp.keepalive;
+        let send_buffer = self.config.tcp.send_buffer;
+        let tcp_listener = stream::select_all(listeners).filter_map(|stream| {
+            future::ready(match stream {
+                Ok(st) => {
+                    st.set_nodelay(true)
+                        .expect("Failed to set stream as nonblocking");
 
-            st.set_nodelay(true)
-                .expect("Failed to set stream as nonblocking");
+                    st.set_keepalive(Some(keepalive))
+                        .expect("Failed to set keepalive");
 
-            st.set_keepalive(Some(Duration::from_secs(1)))
-                .expect("Failed to set keepalive");
+                    if let Some(size) = send_buffer {
+                        st.set_send_buffer_size(size)
+                            .expect("Failed to set send buffer size");
+                    }
 
-            (st, addr)
+                    st.peer_addr().ok().map(|addr| (st, addr))
+                }
+                Err(err) => {
+                    warn!(%err, "failed to accept connection");
+                    None
+                }
+            })
         });
 
-        println!("🚀 Running game server on {}...", addr);
+        if let Some(driver) = self.timer_driver.take() {
+            tokio::spawn(driver.run());
+        }
+
+        info!("🚀 running game server");
+        let ping_interval = self.config.heartbeat.ping_interval;
+        self.sender()
+            .send_every(|| Event::BroadcastLatencies, ping_interval);
+        if let Some(interval) = self.config.metrics_interval {
+            self.sender().send_every(|| Event::ReportMetrics, interval);
+        }
 
+        let mut tcp_listener = Some(tcp_listener);
         loop {
             tokio::select! {
                 Some(event) = self.event_queue.recv_async() => {
                     match event {
-                        Event::CtrlC => break,
+                        Event::CtrlC if self.shutting_down => {
+                            warn!("ctrl-c received again, forcing shutdown");
+                            return Ok(());
+                        }
+                        Event::CtrlC => match self.config.shutdown.countdown.as_secs() {
+                            0 => break,
+                            countdown => self.on_shutdown_tick(countdown),
+                        },
+                        Event::ShutdownTick(0) => break,
+                        Event::ShutdownTick(in_secs) => self.on_shutdown_tick(in_secs),
                         Event::PlayerLeft(player_id) => self.on_client_disconnect(player_id),
                         Event::PassBall(sender, receiver) => self.on_ball_pass(sender, receiver),
+                        Event::HoldExpired(holder) => self.on_hold_expired(holder),
+                        Event::ReloadBans => self.on_reload_bans(),
+                        Event::Latency(player, rtt) => self.on_latency(player, rtt),
+                        Event::BroadcastLatencies => self.on_broadcast_latencies(),
+                        Event::ReportMetrics => self.on_report_metrics(),
                     }
                 }
 
                 // listen and accept incoming connections in async thread.
-                Some((socket, addr)) = tcp_listener.next() => self.on_client_connect(addr, socket),
+                Some((socket, addr)) = next_connection(&mut tcp_listener) => {
+                    self.on_client_connect(addr, socket)
+                }
 
                 // tcp pipe probably closed, stop server
                 else => break,
             };
-        }
 
-        // disconnect players
-        for (_, player) in self.connected_players.drain() {
-            player.send_with_urgency(ToClient::Disconnect("Server Shutdown".into()));
+            if self.shutting_down && tcp_listener.is_some() {
+                info!("no longer accepting connections");
+                tcp_listener = None;
+            }
         }
 
+        self.disconnect_all().await;
         Ok(())
     }
 }
+
+/// encode a message once for every session it goes to
+fn encode_broadcast(msg: &ToClient) -> Option<Outgoing> {
+    match Frame::encode(msg) {
+        Ok(frame) => Some(Outgoing::Encoded(frame)),
+        Err(err) => {
+            error!(%err, ?msg, "failed to encode broadcast, dropping it");
+            None
+        }
+    }
+}
+
+/// queue `msg` on every session, returning the players that can't keep up
+fn send_each<'a>(
+    sessions: impl Iterator<Item = (&'a Player, &'a SessionHandle)>,
+    msg: &Outgoing,
+    priority: Priority,
+) -> Vec<Player> {
+    let overflowed = |session: &SessionHandle| {
+        session.sender.send_with_priority(msg.clone(), priority) == Sent::Overflowed
+    };
+    sessions
+        .filter(|(_, session)| overflowed(session))
+        .map(|(player, _)| *player)
+        .collect()
+}
+
+/// tell a client why it can't join and close the connection
+fn reject(st: TcpStream, msg: ToClient) {
+    let (_, mut writer) = utils::frame_socket::<ToServer, ToClient>(st);
+    tokio::spawn(async move {
+        let _ = writer.send(msg).await;
+    });
+}
+
+/// next accepted connection, or never once the listeners are closed
+async fn next_connection<S>(listener: &mut Option<S>) -> Option<(TcpStream, SocketAddr)>
+where
+    S: Stream<Item = (TcpStream, SocketAddr)> + Unpin,
+{
+    match listener {
+        Some(listener) => listener.next().await,
+        None => future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_latency_reports_fit_a_frame() {
+        let latencies = (0..LATENCIES_PER_MESSAGE)
+            .map(|i| (Player(usize::MAX - i), Duration::new(u64::MAX, 999_999_999)))
+            .collect();
+        assert!(Frame::encode(&ToClient::PlayerLatencies(latencies)).is_ok());
+    }
+}
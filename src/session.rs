@@ -1,26 +1,48 @@
-use std::{
-    net::SocketAddr,
-    time::{Duration, Instant},
-};
+use std::{net::SocketAddr, time::Duration};
 
 use crate::{
-    events::{EventQueue, EventSender},
+    clock::Clock,
+    config::ServerConfig,
+    encoding::{self, Frame},
+    events::{EventQueue, EventSender, Overflow, TimerWheel},
     game::Player,
-    message::{GameState, ToClient, ToServer},
+    latency::Pinger,
+    limits::{MessageLimiter, Verdict},
+    message::{Action, RejectReason, ToClient, ToServer},
     server,
     utils::{MessageReader, MessageWriter},
 };
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{Sink, SinkExt, StreamExt};
+use tracing::{debug, error, warn, Span};
 
 type ClientMessageReader = MessageReader<ToServer>;
 type ClientMessageWriter = MessageWriter<ToClient>;
 
+/// what the server asks a session to send to its client
+#[derive(Clone, Debug)]
+pub enum Outgoing {
+    Message(ToClient),
+    /// already encoded, e.g. a broadcast shared by every session
+    Encoded(Frame<ToClient>),
+}
+
+impl From<ToClient> for Outgoing {
+    fn from(msg: ToClient) -> Self { Outgoing::Message(msg) }
+}
+
 pub struct ClientSession {
     player: Player,
     peer_addr: SocketAddr,
     server: EventSender<server::Event>,
     client_msg_stream: (ClientMessageReader, ClientMessageWriter),
-    event_queue: EventQueue<ToClient>,
+    event_queue: EventQueue<Outgoing>,
+    heartbeat_interval: Duration,
+    heartbeat_timeout: Duration,
+    ping_interval: Duration,
+    pinger: Pinger,
+    limiter: MessageLimiter,
+    /// heartbeat and ping timers, shared with every other session
+    timers: TimerWheel,
     stop: bool,
 }
 
@@ -30,88 +52,157 @@ impl ClientSession {
         peer_addr: SocketAddr,
         server: EventSender<server::Event>,
         client_msg_stream: (ClientMessageReader, ClientMessageWriter),
+        config: &ServerConfig,
+        clock: Clock,
+        timers: TimerWheel,
     ) -> Self {
         Self {
             player: id,
             peer_addr,
             server,
             client_msg_stream,
-            event_queue: EventQueue::default(),
+            // the server notices a full queue when broadcasting and disconnects the client
+            event_queue: EventQueue::bounded(
+                config.connections.send_queue,
+                Overflow::DropNewest,
+                clock.clone(),
+            ),
+            heartbeat_interval: config.heartbeat.interval,
+            heartbeat_timeout: config.heartbeat.timeout,
+            ping_interval: config.heartbeat.ping_interval,
+            pinger: Pinger::new(clock.clone()),
+            limiter: MessageLimiter::new(&config.rate_limit, &clock),
+            timers,
             stop: false,
         }
     }
 
-    pub fn sender(&self) -> &EventSender<ToClient> { self.event_queue.sender() }
+    pub fn sender(&self) -> &EventSender<Outgoing> { self.event_queue.sender() }
 
-    async fn send(&mut self, msg: ToClient) {
+    /// tracing span carrying this session's player and peer address
+    pub fn span(&self) -> Span {
+        tracing::info_span!("session", player = self.player.0, peer = %self.peer_addr)
+    }
+
+    /// write a message or an encoded frame to the client
+    async fn send<M>(&mut self, msg: M)
+    where
+        ClientMessageWriter: Sink<M, Error = encoding::Error>,
+    {
         if let Err(err) = self.client_msg_stream.1.send(msg).await {
-            println!("{}", err);
+            warn!(%err, "failed to send message to client");
             self.stop = true;
         }
     }
 
-    async fn kick(&mut self, reason: String) {
+    /// send the disconnect message and stop
+    async fn kick(&mut self, msg: ToClient) {
         if !self.stop {
             self.stop = true;
-            self.send(ToClient::Disconnect(reason)).await;
+            self.send(msg).await;
         }
     }
 
-    pub async fn start(&mut self, initial_state: GameState) {
-        struct CheckHeartBeat;
-        let mut hb_check: EventQueue<CheckHeartBeat> = EventQueue::default();
-        let mut last_hb = Instant::now();
-
-        // send player initial game state
-        self.send(ToClient::InitialState(self.player, initial_state))
-            .await;
-
-        let timeout_duration = Duration::from_secs(5);
-        hb_check
-            .sender()
-            .send_with_delay(CheckHeartBeat, timeout_duration);
+    pub async fn start(&mut self) {
+        enum Tick {
+            CheckHeartBeat,
+            Heartbeat,
+            Ping,
+        }
+        // the timers stop by themselves once the session and its receiver are gone
+        let (tick_tx, ticks) = flume::unbounded();
+
+        // fires once the client has been silent for the timeout, pushed back on every heartbeat
+        let timeout_duration = self.heartbeat_timeout;
+        let heartbeat_check =
+            self.timers.send_with_delay(&tick_tx, Tick::CheckHeartBeat, timeout_duration);
+        // jittered so sessions that connected together don't tick in lockstep
+        let (heartbeat, ping) = (self.heartbeat_interval, self.ping_interval);
+        self.timers.send_every_with_jitter(&tick_tx, || Tick::Heartbeat, heartbeat, heartbeat / 10);
+        self.timers.send_every_with_jitter(&tick_tx, || Tick::Ping, ping, ping / 10);
+
+        // tell the client how often to expect heartbeats before anything else
+        self.send(ToClient::Hello {
+            heartbeat_interval: self.heartbeat_interval,
+            heartbeat_timeout: timeout_duration,
+        })
+        .await;
 
         while !self.stop {
             let client_msg = self.client_msg_stream.0.next();
             let server_msg = self.event_queue.recv_async();
 
             tokio::select! {
-                _  = hb_check.recv_async() => {
-                    if Instant::now().duration_since(last_hb)
-                        > timeout_duration
-                        {
-                            // heartbeat timed out
-                            log::warn!(
-                                "({}): Client heartbeat failed, disconnecting!",
-                                self.peer_addr
-                                );
-
-                            let _ = self.send(ToClient::Disconnect("Heartbeat failed".to_owned())).await;
-                            break;
-                        }
+                Ok(tick) = ticks.recv_async() => match tick {
+                    Tick::CheckHeartBeat => {
+                        // heartbeat timed out
+                        warn!("client heartbeat failed, disconnecting");
+
+                        let _ = self.send(ToClient::Disconnect("Heartbeat failed".to_owned())).await;
+                        break;
+                    }
+                    Tick::Heartbeat => {
+                        self.send(ToClient::Heartbeat).await;
+                    }
+                    Tick::Ping => {
+                        let (nonce, sent_at) = self.pinger.ping();
+                        self.send(ToClient::Ping(nonce, sent_at)).await;
+                    }
                 },
 
                 Some(msg) = server_msg => {
                     match msg {
-                        ToClient::Disconnect(reason) => self.kick(reason).await,
-                        _ => self.send(msg).await
+                        Outgoing::Message(
+                            msg @ (ToClient::Disconnect(_) | ToClient::Kicked(_)),
+                        ) => self.kick(msg).await,
+                        Outgoing::Message(msg) => self.send(msg).await,
+                        Outgoing::Encoded(frame) => self.send(frame).await,
                     }
                 },
 
-                Some(msg) = client_msg => {
+                msg = client_msg => {
                     match msg {
-                        Ok(msg) =>  {
+                        Some(Ok(msg)) =>  {
+                            match self.limiter.check(msg.kind()) {
+                                Verdict::Allow => {}
+                                Verdict::Throttle { warnings_left } => {
+                                    debug!(kind = ?msg.kind(), warnings_left, "client throttled");
+                                    if let ToServer::PassBall(receiver) = msg {
+                                        let action = Action::PassBall(receiver);
+                                        let reason = RejectReason::RateLimited;
+                                        self.send(ToClient::ActionRejected { action, reason }).await;
+                                    }
+                                    self.send(ToClient::Throttled { kind: msg.kind(), warnings_left }).await;
+                                    continue;
+                                }
+                                Verdict::Kick => {
+                                    warn!(kind = ?msg.kind(), "client kept flooding, disconnecting");
+                                    let reason = "Sending too many messages".to_owned();
+                                    self.kick(ToClient::Kicked(reason)).await;
+                                    break;
+                                }
+                            }
+
                             match msg {
-                                ToServer::Heartbeat => {
-                                    last_hb = Instant::now();
-                                    hb_check.sender().send_with_delay(CheckHeartBeat, timeout_duration);
-                                },
+                                ToServer::Heartbeat => heartbeat_check.reschedule(timeout_duration),
+                                ToServer::Ping(nonce, sent_at) => self.send(ToClient::Pong(nonce, sent_at)).await,
+                                ToServer::Pong(nonce, _) => {
+                                    if let Some(rtt) = self.pinger.on_pong(nonce) {
+                                        self.server.send(server::Event::Latency(self.player, rtt));
+                                    }
+                                }
                                 ToServer::Leave => break,
-                                ToServer::PassBall(receiver) => self.server.send(server::Event::PassBall(self.player, receiver)),
+                                ToServer::PassBall(receiver) => {
+                                    self.server.send(server::Event::PassBall(self.player, receiver));
+                                }
                             };
                         }
-                        Err(err) => {
-                            log::error!("decode err {:?}", err);
+                        Some(Err(err)) => {
+                            error!(?err, "failed to decode client message");
+                            break;
+                        }
+                        None => {
+                            // client closed the connection
                             break;
                         }
                     }
@@ -121,6 +212,10 @@ impl ClientSession {
         }
         self.stop = true;
 
+        // flush anything still buffered, e.g. the disconnect reason, and close the socket
+        let _ = SinkExt::<ToClient>::close(&mut self.client_msg_stream.1).await;
+        debug!("session closed");
+
         // notify server
         self.server.send(server::Event::PlayerLeft(self.player));
     }
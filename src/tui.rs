@@ -0,0 +1,432 @@
+use std::{
+    collections::HashMap,
+    io::{self, Stdout},
+    panic::PanicHookInfo,
+    sync::Arc,
+    time::Duration,
+};
+
+use crossterm::{
+    event::{self, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, Paragraph},
+    Frame, Terminal,
+};
+
+use crate::{
+    client::Event,
+    events::EventSender,
+    game::Player,
+};
+
+/// number of event log lines kept for scrolling back
+const LOG_CAPACITY: usize = 1000;
+
+/// what the TUI shows besides its own event log and input line
+pub struct View<'a> {
+    pub server_addr: &'a str,
+    pub connection: String,
+    pub myself: Option<Player>,
+    pub holder: Option<Player>,
+    pub players: &'a [Player],
+    /// round trip time of each player to the server
+    pub latencies: Option<&'a HashMap<Player, Duration>>,
+    /// our own round trip time to the server
+    pub latency: Option<Duration>,
+}
+
+/// outcome of a key press
+pub enum KeyAction {
+    None,
+    /// the user entered a line
+    Submit(String),
+    Quit,
+}
+
+/// tab completion in progress, cycling through `candidates`
+struct Completion {
+    stem: String,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+/// the input line with its history and tab completion
+#[derive(Default)]
+struct Prompt {
+    input: String,
+    history: Vec<String>,
+    history_pos: Option<usize>,
+    completion: Option<Completion>,
+}
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// full-screen terminal UI, restores the terminal when dropped
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    log: Vec<String>,
+    /// lines scrolled back from the newest log entry
+    scroll: usize,
+    prompt: Prompt,
+    /// the panic hook to put back once the terminal is restored
+    previous_hook: Arc<PanicHook>,
+}
+
+impl Tui {
+    /// switch the terminal to raw mode on the alternate screen
+    pub fn start() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+
+        // leave raw mode before a panic message is printed
+        let previous_hook = Arc::new(std::panic::take_hook());
+        let hook = previous_hook.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info);
+        }));
+
+        Ok(Self {
+            terminal: Terminal::new(CrosstermBackend::new(io::stdout()))?,
+            log: Vec::new(),
+            scroll: 0,
+            prompt: Prompt::default(),
+            previous_hook,
+        })
+    }
+
+    /// read terminal input on a separate thread and forward it to the app
+    pub fn spawn_input(sender: EventSender<Event>) {
+        std::thread::spawn(move || loop {
+            match event::read() {
+                Ok(event::Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                    sender.send(Event::Key(key));
+                }
+                Ok(event::Event::Resize(..)) => {
+                    sender.send(Event::Redraw);
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    sender.send_with_urgency(Event::CtrlC);
+                    break;
+                }
+            }
+        });
+    }
+
+    pub fn print(&mut self, line: String) {
+        self.log.push(line);
+        if self.log.len() > LOG_CAPACITY {
+            self.log.remove(0);
+        }
+
+        // keep the view still while the user is scrolled back
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent, players: &[Player], commands: &[&str]) -> KeyAction {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => return KeyAction::Quit,
+            KeyCode::PageUp => {
+                self.scroll = (self.scroll + 10).min(self.log.len().saturating_sub(1))
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::End => self.scroll = 0,
+            KeyCode::Enter => {
+                self.scroll = 0;
+                if let Some(line) = self.prompt.submit() {
+                    return KeyAction::Submit(line);
+                }
+            }
+            code => self.prompt.edit(code, players, commands),
+        }
+
+        KeyAction::None
+    }
+
+    pub fn draw(&mut self, view: &View) -> io::Result<()> {
+        let Self {
+            terminal,
+            log,
+            scroll,
+            prompt,
+            ..
+        } = self;
+
+        terminal.draw(|frame| {
+            let [main, input_area, status] = Layout::vertical([
+                Constraint::Min(3),
+                Constraint::Length(3),
+                Constraint::Length(1),
+            ])
+            .areas(frame.area());
+            let [log_area, players_area] =
+                Layout::horizontal([Constraint::Min(20), Constraint::Length(26)]).areas(main);
+
+            draw_log(frame, log_area, log, *scroll);
+            draw_players(frame, players_area, view);
+            draw_input(frame, input_area, &prompt.input);
+            draw_status(frame, status, view);
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        restore();
+        // the hook can't be swapped while panicking, it restores the terminal again anyway
+        if !std::thread::panicking() {
+            let previous = self.previous_hook.clone();
+            std::panic::set_hook(Box::new(move |info| previous(info)));
+        }
+    }
+}
+
+impl Prompt {
+    /// take the entered line, remembering it in the history
+    fn submit(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.input);
+        self.history_pos = None;
+        self.completion = None;
+        if line.trim().is_empty() {
+            return None;
+        }
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        Some(line)
+    }
+
+    fn edit(&mut self, code: KeyCode, players: &[Player], commands: &[&str]) {
+        if code != KeyCode::Tab {
+            self.completion = None;
+        }
+
+        match code {
+            KeyCode::Char(c) => {
+                self.input.push(c);
+                self.history_pos = None;
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Esc => self.input.clear(),
+            KeyCode::Up if !self.history.is_empty() => {
+                let pos = match self.history_pos {
+                    Some(pos) => pos.saturating_sub(1),
+                    None => self.history.len() - 1,
+                };
+                self.history_pos = Some(pos);
+                self.input = self.history[pos].clone();
+            }
+            KeyCode::Down => {
+                if let Some(pos) = self.history_pos {
+                    if pos + 1 < self.history.len() {
+                        self.history_pos = Some(pos + 1);
+                        self.input = self.history[pos + 1].clone();
+                    } else {
+                        self.history_pos = None;
+                        self.input.clear();
+                    }
+                }
+            }
+            KeyCode::Tab => self.complete(players, commands),
+            _ => {}
+        }
+    }
+
+    /// complete the word under the cursor: a command first, player IDs and
+    /// targets like `next` after it.
+    /// Pressing tab again cycles through the other candidates.
+    fn complete(&mut self, players: &[Player], commands: &[&str]) {
+        if let Some(completion) = &mut self.completion {
+            completion.index = (completion.index + 1) % completion.candidates.len();
+            self.input = format!("{}{}", completion.stem, completion.candidates[completion.index]);
+            return;
+        }
+
+        let split = self.input.rfind(' ').map_or(0, |i| i + 1);
+        let (stem, word) = self.input.split_at(split);
+
+        let candidates: Vec<String> = if stem.trim().is_empty() {
+            commands
+                .iter()
+                .filter(|cmd| cmd.starts_with(word))
+                .map(|cmd| format!("{} ", cmd))
+                .collect()
+        } else {
+            let mut ids: Vec<usize> = players.iter().map(|p| p.0).collect();
+            ids.sort_unstable();
+            let word = word.trim_start_matches('#');
+            let keywords = ["next", "random", "me"].iter().map(|kw| kw.to_string());
+            ids.iter()
+                .map(ToString::to_string)
+                .chain(keywords)
+                .filter(|arg| arg.starts_with(word))
+                .collect()
+        };
+
+        if let Some(first) = candidates.first() {
+            let stem = stem.to_owned();
+            self.input = format!("{}{}", stem, first);
+            if candidates.len() > 1 {
+                self.completion = Some(Completion {
+                    stem,
+                    candidates,
+                    index: 0,
+                });
+            }
+        }
+    }
+}
+
+fn restore() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+fn draw_log(frame: &mut Frame, area: Rect, log: &[String], scroll: usize) {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = log.len().saturating_sub(scroll);
+    let start = end.saturating_sub(height);
+    let items: Vec<ListItem> = log[start..end]
+        .iter()
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+
+    let title = if scroll > 0 {
+        format!(" Events (scrolled back {}, End to follow) ", scroll)
+    } else {
+        " Events ".to_owned()
+    };
+    frame.render_widget(List::new(items).block(Block::bordered().title(title)), area);
+}
+
+fn draw_players(frame: &mut Frame, area: Rect, view: &View) {
+    let mut players = view.players.to_vec();
+    players.sort_by_key(|p| p.0);
+
+    let items: Vec<ListItem> = players
+        .iter()
+        .map(|player| {
+            let mut style = Style::default();
+            let mut label = format!("#{}", player.0);
+            if Some(*player) == view.myself {
+                label.push_str(" (you)");
+                style = style.fg(Color::Cyan);
+            }
+            if Some(*player) == view.holder {
+                label.push_str(" ●");
+                style = style.fg(Color::Yellow).add_modifier(Modifier::BOLD);
+            }
+            if let Some(rtt) = view.latencies.and_then(|latencies| latencies.get(player)) {
+                label.push_str(&format!(" {}ms", rtt.as_millis()));
+            }
+            ListItem::new(label).style(style)
+        })
+        .collect();
+
+    let title = format!(" Players ({}) ", players.len());
+    frame.render_widget(List::new(items).block(Block::bordered().title(title)), area);
+}
+
+fn draw_input(frame: &mut Frame, area: Rect, input: &str) {
+    let line = Line::from(vec![Span::styled("> ", Style::default().fg(Color::DarkGray)), Span::raw(input)]);
+    frame.render_widget(Paragraph::new(line).block(Block::bordered()), area);
+
+    let x = area.x + 3 + input.chars().count() as u16;
+    frame.set_cursor_position((x.min(area.right().saturating_sub(2)), area.y + 1));
+}
+
+fn draw_status(frame: &mut Frame, area: Rect, view: &View) {
+    let latency = match view.latency {
+        Some(latency) => format!("{}ms", latency.as_millis()),
+        None => "–".to_owned(),
+    };
+    let you = match view.myself {
+        Some(player) => player.to_string(),
+        None => "–".to_owned(),
+    };
+
+    let status = format!(
+        " {} │ {} │ you: {} │ latency: {} │ Tab complete, PgUp/PgDn scroll, Ctrl-C quit",
+        view.server_addr, view.connection, you, latency
+    );
+    let style = Style::default().bg(Color::Blue).fg(Color::White);
+    frame.render_widget(Paragraph::new(status).style(style), area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS: &[&str] = &["pass", "players", "quit"];
+
+    fn typed(text: &str) -> Prompt {
+        let mut prompt = Prompt::default();
+        text.chars().for_each(|c| prompt.edit(KeyCode::Char(c), &[], COMMANDS));
+        prompt
+    }
+
+    #[test]
+    fn history_recalls_submitted_lines() {
+        let mut prompt = Prompt::default();
+        for line in ["pass 2", "pass 2", "players", " "] {
+            line.chars().for_each(|c| prompt.edit(KeyCode::Char(c), &[], COMMANDS));
+            prompt.submit();
+        }
+        assert_eq!(prompt.history, vec!["pass 2", "players"]);
+
+        prompt.edit(KeyCode::Up, &[], COMMANDS);
+        assert_eq!(prompt.input, "players");
+        prompt.edit(KeyCode::Up, &[], COMMANDS);
+        prompt.edit(KeyCode::Up, &[], COMMANDS);
+        assert_eq!(prompt.input, "pass 2");
+        prompt.edit(KeyCode::Down, &[], COMMANDS);
+        assert_eq!(prompt.input, "players");
+        prompt.edit(KeyCode::Down, &[], COMMANDS);
+        assert_eq!(prompt.input, "");
+    }
+
+    #[test]
+    fn tab_completes_commands_and_cycles() {
+        let mut prompt = typed("p");
+        prompt.edit(KeyCode::Tab, &[], COMMANDS);
+        assert_eq!(prompt.input, "pass ");
+        prompt.edit(KeyCode::Tab, &[], COMMANDS);
+        assert_eq!(prompt.input, "players ");
+        prompt.edit(KeyCode::Tab, &[], COMMANDS);
+        assert_eq!(prompt.input, "pass ");
+
+        let mut prompt = typed("q");
+        prompt.edit(KeyCode::Tab, &[], COMMANDS);
+        assert_eq!(prompt.input, "quit ");
+    }
+
+    #[test]
+    fn tab_completes_players_and_targets() {
+        let players = [Player(12), Player(3), Player(1)];
+        let mut prompt = typed("pass #1");
+        prompt.edit(KeyCode::Tab, &players, COMMANDS);
+        assert_eq!(prompt.input, "pass 1");
+        prompt.edit(KeyCode::Tab, &players, COMMANDS);
+        assert_eq!(prompt.input, "pass 12");
+
+        let mut prompt = typed("pass n");
+        prompt.edit(KeyCode::Tab, &players, COMMANDS);
+        assert_eq!(prompt.input, "pass next");
+        assert!(prompt.completion.is_none(), "a single candidate needs no cycling");
+    }
+}
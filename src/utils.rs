@@ -1,6 +1,8 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
 use tokio::{
     io::{ReadHalf, WriteHalf},
-    net::TcpStream,
+    net::{TcpListener, TcpStream},
 };
 use tokio_util::codec::{FramedRead, FramedWrite};
 
@@ -20,3 +22,20 @@ where
         FramedWrite::new(w, NetworkMessage::<W>::new()),
     )
 }
+
+/// bind a listener on `addr`.
+///
+/// IPv6 listeners are restricted to IPv6 so `::` and `0.0.0.0` can be bound
+/// side by side on the same port.
+pub fn bind_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
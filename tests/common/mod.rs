@@ -0,0 +1,233 @@
+//! in-process test harness: a `GameServer` on an ephemeral port and scripted
+//! clients speaking the raw protocol.
+#![allow(dead_code)]
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use ball_pass_game::{
+    clock::{Clock, SimulatedClock},
+    config::ServerConfig,
+    events::EventSender,
+    game::Player,
+    message::{ToClient, ToServer},
+    server::{self, GameServer},
+    utils,
+};
+use futures_util::{SinkExt, StreamExt};
+use socket2::{Domain, Socket, Type};
+use tokio::{net::TcpStream, task::JoinHandle};
+
+/// how long to wait for an expected message before failing the test
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// expect `$client` to eventually receive a message matching the pattern,
+/// skipping anything else, and return it
+#[macro_export]
+macro_rules! assert_receives {
+    ($client:expr, $pattern:pat $(if $guard:expr)?) => {
+        $client
+            .receives(stringify!($pattern $(if $guard)?), |msg| {
+                matches!(msg, $pattern $(if $guard)?)
+            })
+            .await
+    };
+}
+
+/// a running server listening on an ephemeral localhost port
+pub struct TestServer {
+    pub addr: SocketAddr,
+    sender: EventSender<server::Event>,
+    task: JoinHandle<io::Result<()>>,
+    clock: Option<SimulatedClock>,
+}
+
+impl TestServer {
+    /// start a server with the default config
+    pub async fn start() -> Self { Self::with_config(ServerConfig::default()).await }
+
+    /// start a server with `config`, ignoring its bind addresses and port
+    pub async fn with_config(config: ServerConfig) -> Self { Self::start_with(config, None).await }
+
+    /// start a server whose timers only run when the test calls `advance`
+    pub async fn simulated(config: ServerConfig) -> Self {
+        Self::start_with(config, Some(SimulatedClock::default())).await
+    }
+
+    async fn start_with(config: ServerConfig, clock: Option<SimulatedClock>) -> Self {
+        let mut server = GameServer::builder()
+            .config(config)
+            .clock(clock.clone().map_or(Clock::System, Clock::Simulated))
+            .bind(vec!["127.0.0.1".parse().unwrap()])
+            .port(0)
+            .build()
+            .expect("invalid test server config");
+        let addr = server.listen().expect("failed to bind test server")[0];
+        let sender = server.sender().clone();
+        let task = tokio::spawn(server.run());
+
+        Self {
+            addr,
+            sender,
+            task,
+            clock,
+        }
+    }
+
+    /// fast-forward the simulated clock, firing the server's timers on the way
+    pub async fn advance(&self, by: Duration) {
+        let clock = self.clock.as_ref().expect("server is not simulated");
+        clock.advance(by).await;
+    }
+
+    pub fn send(&self, event: server::Event) { self.sender.send(event); }
+
+    /// connect a client that answers heartbeats and pings
+    pub async fn connect(&self) -> TestClient { TestClient::connect(self.addr, true).await }
+
+    /// connect a client and wait until it joined the game
+    pub async fn join(&self) -> TestClient {
+        let mut client = self.connect().await;
+        client.joined().await;
+        client
+    }
+
+    /// connect a client that never sends heartbeats on its own
+    pub async fn connect_silent(&self) -> TestClient { TestClient::connect(self.addr, false).await }
+
+    /// open a connection that is never read from, so the server's messages pile
+    /// up. Its receive buffer is kept small so that happens quickly.
+    pub async fn connect_stalled(&self) -> TcpStream {
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        socket.set_recv_buffer_size(4096).unwrap();
+        socket.connect(&self.addr.into()).expect("failed to connect");
+        socket.set_nonblocking(true).unwrap();
+        TcpStream::from_std(socket.into()).unwrap()
+    }
+
+    /// wait for the server to stop
+    pub async fn stopped(self) -> io::Result<()> {
+        tokio::time::timeout(RECV_TIMEOUT, self.task)
+            .await
+            .expect("server did not stop in time")
+            .expect("server task panicked")
+    }
+}
+
+/// a scripted client. A background task reads the socket so the client keeps
+/// answering heartbeats and pings even while the test isn't looking at it.
+pub struct TestClient {
+    pub player: Option<Player>,
+    to_server: flume::Sender<ToServer>,
+    from_server: flume::Receiver<ToClient>,
+    /// every message received so far, for failure reports
+    history: Vec<ToClient>,
+}
+
+impl TestClient {
+    async fn connect(addr: SocketAddr, respond: bool) -> Self {
+        let stream = TcpStream::connect(addr).await.expect("failed to connect");
+        let (mut reader, mut writer) = utils::frame_socket::<ToClient, ToServer>(stream);
+        let (to_server, outgoing) = flume::unbounded();
+        let (incoming, from_server) = flume::unbounded();
+
+        tokio::spawn(async move {
+            loop {
+                let msg = tokio::select! {
+                    msg = reader.next() => match msg {
+                        Some(Ok(msg)) => msg,
+                        _ => break,
+                    },
+                    msg = outgoing.recv_async() => match msg {
+                        Ok(msg) => {
+                            if writer.send(msg).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        // the test dropped the client
+                        Err(_) => break,
+                    },
+                };
+
+                let reply = match &msg {
+                    ToClient::Heartbeat if respond => Some(ToServer::Heartbeat),
+                    ToClient::Ping(nonce, sent_at) if respond => Some(ToServer::Pong(*nonce, *sent_at)),
+                    _ => None,
+                };
+                if let Some(reply) = reply {
+                    if writer.send(reply).await.is_err() {
+                        break;
+                    }
+                }
+
+                if incoming.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            player: None,
+            to_server,
+            from_server,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn send(&self, msg: ToServer) { self.to_server.send(msg).expect("client task stopped") }
+
+    pub fn pass_to(&self, player: Player) { self.send(ToServer::PassBall(player)) }
+
+    /// every message received so far
+    pub fn history(&self) -> &[ToClient] { &self.history }
+
+    /// this client's player, once it joined
+    pub fn me(&self) -> Player { self.player.expect("client has not joined the game") }
+
+    /// wait for the initial state and remember our player
+    pub async fn joined(&mut self) -> Player {
+        let player = match assert_receives!(self, ToClient::InitialState(..)) {
+            ToClient::InitialState(player, _) => player,
+            _ => unreachable!(),
+        };
+        self.player = Some(player);
+        player
+    }
+
+    /// skip messages until one matches `accept`, failing after `RECV_TIMEOUT`
+    pub async fn receives(&mut self, expected: &str, accept: impl Fn(&ToClient) -> bool) -> ToClient {
+        let deadline = tokio::time::Instant::now() + RECV_TIMEOUT;
+        loop {
+            let msg = match tokio::time::timeout_at(deadline, self.from_server.recv_async()).await {
+                Ok(Ok(msg)) => msg,
+                Ok(Err(_)) => panic!(
+                    "{:?}: connection closed while waiting for {}, received {:#?}",
+                    self.player, expected, self.history
+                ),
+                Err(_) => panic!(
+                    "{:?}: timed out waiting for {}, received {:#?}",
+                    self.player, expected, self.history
+                ),
+            };
+            self.history.push(msg.clone());
+            if accept(&msg) {
+                return msg;
+            }
+        }
+    }
+
+    /// wait for the server to close the connection
+    pub async fn closed(&mut self) {
+        let deadline = tokio::time::Instant::now() + RECV_TIMEOUT;
+        loop {
+            match tokio::time::timeout_at(deadline, self.from_server.recv_async()).await {
+                Ok(Ok(msg)) => self.history.push(msg),
+                Ok(Err(_)) => return,
+                Err(_) => panic!(
+                    "{:?}: connection still open, received {:#?}",
+                    self.player, self.history
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+mod common;
+
+use std::time::Duration;
+
+use ball_pass_game::{
+    config::{RateLimit, ServerConfig},
+    connection::{Client, ConnectOptions, ConnectionEvent},
+    game::Player,
+    message::{ToClient, ToServer, WhoPassed},
+    server,
+};
+use common::TestServer;
+
+#[tokio::test]
+async fn first_player_gets_the_ball() {
+    let server = TestServer::start().await;
+    let mut one = server.connect().await;
+
+    assert_receives!(one, ToClient::Hello { .. });
+    assert_eq!(one.joined().await, Player(1));
+    assert_receives!(one, ToClient::PassBall(Player(1), WhoPassed::PlayerStumbledUponBall));
+}
+
+#[tokio::test]
+async fn players_are_told_about_joins() {
+    let server = TestServer::start().await;
+    let mut one = server.join().await;
+    let mut two = server.connect().await;
+
+    match assert_receives!(two, ToClient::InitialState(..)) {
+        ToClient::InitialState(player, state) => {
+            assert_eq!(player, Player(2));
+            assert_eq!(state.players, vec![Player(1)]);
+            assert_eq!(state.info.player_with_ball, Some(Player(1)));
+        }
+        _ => unreachable!(),
+    }
+    assert_receives!(one, ToClient::PlayerJoin(Player(2)));
+    assert_receives!(two, ToClient::PlayerJoin(Player(2)));
+}
+
+#[tokio::test]
+async fn pass_is_broadcast() {
+    let server = TestServer::start().await;
+    let mut one = server.join().await;
+    let mut two = server.join().await;
+    let mut three = server.join().await;
+
+    one.pass_to(three.me());
+    assert_receives!(two, ToClient::PassBall(Player(3), WhoPassed::Player));
+    assert_receives!(three, ToClient::PassBall(Player(3), WhoPassed::Player));
+
+    // only the holder may pass
+    one.pass_to(two.me());
+    assert_receives!(one, ToClient::ActionRejected { .. });
+}
+
+#[tokio::test]
+async fn holder_leaving_passes_the_ball_on() {
+    let server = TestServer::start().await;
+    let mut one = server.join().await;
+    let mut two = server.join().await;
+    let mut three = server.join().await;
+    assert_receives!(one, ToClient::PassBall(Player(1), _));
+
+    one.send(ToServer::Leave);
+    one.closed().await;
+
+    assert_receives!(two, ToClient::PlayerLeave(Player(1)));
+    assert_receives!(two, ToClient::PassBall(Player(2), WhoPassed::PlayerWithBallLeft));
+    assert_receives!(three, ToClient::PassBall(Player(2), WhoPassed::PlayerWithBallLeft));
+}
+
+#[tokio::test]
+async fn waiting_players_join_first_come_first_served() {
+    let config = ServerConfig {
+        max_players: Some(1),
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(config).await;
+    let mut one = server.join().await;
+    let mut two = server.connect().await;
+    assert_receives!(two, ToClient::Queued { position: 1 });
+    let mut three = server.connect().await;
+    assert_receives!(three, ToClient::Queued { position: 2 });
+
+    one.send(ToServer::Leave);
+    one.closed().await;
+    assert_eq!(two.joined().await, Player(2));
+    assert_receives!(three, ToClient::Queued { position: 1 });
+
+    two.send(ToServer::Leave);
+    two.closed().await;
+    assert_eq!(three.joined().await, Player(3));
+}
+
+#[tokio::test]
+async fn connections_per_address_are_limited() {
+    let mut config = ServerConfig::default();
+    config.connections.max_per_ip = Some(2);
+    let server = TestServer::with_config(config).await;
+    let mut one = server.join().await;
+    let mut two = server.join().await;
+
+    let mut three = server.connect().await;
+    assert_receives!(three, ToClient::Disconnect(reason) if reason.starts_with("Too many"));
+    three.closed().await;
+
+    // leaving frees the slot, and the rejected connection used up no player ID
+    one.send(ToServer::Leave);
+    one.closed().await;
+    assert_receives!(two, ToClient::PlayerLeave(Player(1)));
+    assert_eq!(server.join().await.me(), Player(3));
+}
+
+#[tokio::test]
+async fn silent_client_is_kicked() {
+    let mut config = ServerConfig::default();
+    config.heartbeat.interval = Duration::from_millis(100);
+    config.heartbeat.timeout = Duration::from_millis(300);
+    config.rate_limit.heartbeat = None;
+    config.rate_limit.ping = None;
+    let server = TestServer::with_config(config).await;
+
+    let mut alive = server.join().await;
+    let mut silent = server.connect_silent().await;
+    silent.joined().await;
+
+    assert_receives!(alive, ToClient::PlayerLeave(Player(2)));
+    assert_receives!(silent, ToClient::Disconnect(reason) if reason == "Heartbeat failed");
+    silent.closed().await;
+
+    // the ball holder answering heartbeats stays in the game
+    alive.send(ToServer::PassBall(Player(1)));
+    assert_receives!(alive, ToClient::PassBall(Player(1), WhoPassed::Player));
+}
+
+#[tokio::test]
+async fn short_heartbeat_intervals_fit_the_rate_limits() {
+    let mut config = ServerConfig::default();
+    config.heartbeat.interval = Duration::from_millis(100);
+    config.heartbeat.timeout = Duration::from_millis(400);
+    config.heartbeat.ping_interval = Duration::from_millis(100);
+    config.rate_limit.heartbeat = Some(RateLimit { rate: 15.0, burst: 5 });
+    config.rate_limit.ping = Some(RateLimit { rate: 25.0, burst: 5 });
+    config.validate().expect("limits fit the intervals");
+    let server = TestServer::with_config(config).await;
+
+    // the real client sends a heartbeat and a ping every tick and answers server pings
+    let mut client = Client::connect(ConnectOptions::new(server.addr.to_string()));
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(1500);
+    while let Ok(event) = tokio::time::timeout_at(deadline, client.recv()).await {
+        match event.expect("client stopped") {
+            ConnectionEvent::Message(ToClient::Throttled { kind, .. }) => {
+                panic!("honest client throttled for {:?}", kind)
+            }
+            ConnectionEvent::Message(ToClient::Disconnect(reason))
+            | ConnectionEvent::Reconnecting { reason, .. }
+            | ConnectionEvent::Dropped(reason) => {
+                panic!("honest client lost its connection: {}", reason)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn kicked_clients_do_not_reconnect() {
+    let mut config = ServerConfig::default();
+    config.rate_limit.pass_ball = Some(RateLimit { rate: 0.1, burst: 1 });
+    config.rate_limit.max_violations = 1;
+    let server = TestServer::with_config(config).await;
+
+    let mut options = ConnectOptions::new(server.addr.to_string());
+    options.reconnect = true;
+    let mut client = Client::connect(options);
+    let mut kicked = false;
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), client.recv()).await;
+        match event.expect("client neither kicked nor dropped").expect("client stopped") {
+            ConnectionEvent::Message(ToClient::InitialState(me, _)) => {
+                client.send(ToServer::PassBall(me));
+                client.send(ToServer::PassBall(me));
+            }
+            ConnectionEvent::Message(ToClient::Kicked(_)) => kicked = true,
+            ConnectionEvent::Reconnecting { .. } => panic!("kicked client reconnects"),
+            ConnectionEvent::Dropped(_) => break,
+            _ => {}
+        }
+    }
+    assert!(kicked);
+}
+
+#[tokio::test]
+async fn client_that_stops_reading_is_disconnected() {
+    let mut config = ServerConfig::default();
+    config.connections.send_queue = 64;
+    config.tcp.send_buffer = Some(4096);
+    config.rate_limit.pass_ball = None;
+    let server = TestServer::with_config(config).await;
+
+    let mut one = server.join().await;
+    let _stalled = server.connect_stalled().await;
+    assert_receives!(one, ToClient::PlayerJoin(Player(2)));
+
+    // pass to ourselves until the stalled client's socket and queue are full,
+    // waiting for our own passes so only the stalled client falls behind
+    let dropped = |msg: &ToClient| matches!(msg, ToClient::PlayerLeave(Player(2)));
+    let mut batches = 0;
+    while !one.history().iter().any(dropped) {
+        batches += 1;
+        assert!(batches < 100, "stalled client was never disconnected");
+        for _ in 0..32 {
+            one.pass_to(Player(1));
+        }
+        for _ in 0..32 {
+            assert_receives!(one, ToClient::PassBall(Player(1), WhoPassed::Player));
+        }
+    }
+
+    // the game goes on without them
+    one.pass_to(Player(1));
+    assert_receives!(one, ToClient::PassBall(Player(1), WhoPassed::Player));
+}
+
+#[tokio::test]
+async fn shutdown_counts_down_and_disconnects_everyone() {
+    let mut config = ServerConfig::default();
+    config.shutdown.countdown = Duration::from_secs(1);
+    let server = TestServer::with_config(config).await;
+
+    let mut one = server.join().await;
+    let mut two = server.join().await;
+
+    server.send(server::Event::CtrlC);
+    assert_receives!(one, ToClient::ShuttingDown { in_secs: 1 });
+    assert_receives!(two, ToClient::ShuttingDown { in_secs: 1 });
+
+    // passes are refused during the countdown
+    one.pass_to(Player(2));
+    assert_receives!(one, ToClient::ActionRejected { .. });
+
+    assert_receives!(one, ToClient::Disconnect(reason) if reason == "Server Shutdown");
+    assert_receives!(two, ToClient::Disconnect(reason) if reason == "Server Shutdown");
+    one.closed().await;
+    two.closed().await;
+    server.stopped().await.unwrap();
+}
@@ -0,0 +1,94 @@
+//! timeouts and timers driven by a simulated clock, fast-forwarded by the test
+mod common;
+
+use std::time::Duration;
+
+use ball_pass_game::{
+    config::{GameMode, ServerConfig},
+    game::Player,
+    message::{ToClient, WhoPassed},
+    server,
+};
+use common::TestServer;
+
+#[tokio::test]
+async fn silent_client_is_kicked_after_the_timeout() {
+    let server = TestServer::simulated(ServerConfig::default()).await;
+    let mut alive = server.join().await;
+    let mut silent = server.connect_silent().await;
+    silent.joined().await;
+
+    // the default timeout is 5s, nothing happens before it
+    server.advance(Duration::from_millis(4900)).await;
+    alive.pass_to(Player(2));
+    assert_receives!(silent, ToClient::PassBall(Player(2), WhoPassed::Player));
+
+    server.advance(Duration::from_millis(200)).await;
+    assert_receives!(silent, ToClient::Disconnect(reason) if reason == "Heartbeat failed");
+    assert_receives!(alive, ToClient::PlayerLeave(Player(2)));
+    assert_receives!(alive, ToClient::PassBall(Player(1), WhoPassed::PlayerWithBallLeft));
+
+    // answering heartbeats keeps a client in the game indefinitely
+    server.advance(Duration::from_secs(60)).await;
+    alive.pass_to(Player(1));
+    assert_receives!(alive, ToClient::PassBall(Player(1), WhoPassed::Player));
+}
+
+#[tokio::test]
+async fn hot_potato_passes_on_after_the_hold_limit() {
+    let mut config = ServerConfig::default();
+    config.game.mode = GameMode::HotPotato;
+    let server = TestServer::simulated(config).await;
+    let mut one = server.join().await;
+    let _two = server.join().await;
+
+    server.advance(Duration::from_secs(9)).await;
+    one.pass_to(Player(1));
+    assert_receives!(one, ToClient::PassBall(Player(1), WhoPassed::Player));
+
+    // the pass restarted the 10s hold limit
+    server.advance(Duration::from_secs(9)).await;
+    one.pass_to(Player(1));
+    assert_receives!(one, ToClient::PassBall(Player(1), WhoPassed::Player));
+
+    server.advance(Duration::from_secs(10)).await;
+    assert_receives!(one, ToClient::PassBall(Player(2), WhoPassed::HoldLimitReached));
+}
+
+#[tokio::test]
+async fn shutdown_countdown_ticks_every_second() {
+    let server = TestServer::simulated(ServerConfig::default()).await;
+    let mut one = server.join().await;
+
+    server.send(server::Event::CtrlC);
+    for in_secs in (1..=5).rev() {
+        assert_receives!(one, ToClient::ShuttingDown { in_secs: secs } if *secs == in_secs);
+        server.advance(Duration::from_secs(1)).await;
+    }
+
+    assert_receives!(one, ToClient::Disconnect(reason) if reason == "Server Shutdown");
+    one.closed().await;
+    server.stopped().await.unwrap();
+}
+
+#[tokio::test]
+async fn joins_are_refused_until_the_burst_refills() {
+    let mut config = ServerConfig::default();
+    config.connections.join_rate = Some(0.5);
+    config.connections.join_burst = 2;
+    let server = TestServer::simulated(config).await;
+    let _one = server.join().await;
+    let _two = server.join().await;
+
+    let mut three = server.connect().await;
+    assert_receives!(three, ToClient::Disconnect(reason) if reason.starts_with("Joining too"));
+    three.closed().await;
+
+    // one join every two seconds
+    server.advance(Duration::from_secs(1)).await;
+    let mut four = server.connect().await;
+    assert_receives!(four, ToClient::Disconnect(reason) if reason.starts_with("Joining too"));
+
+    server.advance(Duration::from_secs(1)).await;
+    assert_eq!(server.join().await.me(), Player(3));
+}